@@ -0,0 +1,196 @@
+use std::path::Path;
+
+use crate::synthesis::split_sentences;
+use crate::tts_engine::{TtsError, TtsErrorKind, WordTiming};
+use crate::wav::read_wav_samples;
+
+/// Length of the analysis frames used for silence detection
+const FRAME_MS: u64 = 10;
+/// Pauses shorter than this are treated as part of the speech around them
+const MIN_PAUSE_MS: u64 = 120;
+/// Speech shorter than this is a click, not a word
+const MIN_SPEECH_MS: u64 = 30;
+/// Frames quieter than this fraction of the loud (95th percentile) level are silent
+const SILENCE_RATIO: f32 = 0.1;
+
+/// A stretch of audio containing speech, in milliseconds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Span {
+    start_ms: u64,
+    end_ms: u64,
+}
+
+/// Find the stretches of speech between pauses
+fn detect_speech(samples: &[i16], channels: u16, sample_rate: u32) -> Vec<Span> {
+    let frame_len = (sample_rate as u64 * FRAME_MS / 1000) as usize * channels.max(1) as usize;
+    if frame_len == 0 {
+        return Vec::new();
+    }
+
+    let levels: Vec<f32> = samples
+        .chunks(frame_len)
+        .map(|frame| {
+            let sum: f64 = frame.iter().map(|&s| (s as f64 / i16::MAX as f64).powi(2)).sum();
+            (sum / frame.len() as f64).sqrt() as f32
+        })
+        .collect();
+
+    let mut sorted = levels.clone();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let loud = sorted.get(sorted.len() * 95 / 100).copied().unwrap_or(0.0);
+    let threshold = loud * SILENCE_RATIO;
+    if loud == 0.0 {
+        return Vec::new();
+    }
+
+    // Runs of loud frames
+    let mut spans: Vec<Span> = Vec::new();
+    let mut run_start: Option<usize> = None;
+    for (i, &level) in levels.iter().chain(std::iter::once(&0.0)).enumerate() {
+        match (level > threshold, run_start) {
+            (true, None) => run_start = Some(i),
+            (false, Some(start)) => {
+                spans.push(Span {
+                    start_ms: start as u64 * FRAME_MS,
+                    end_ms: i as u64 * FRAME_MS,
+                });
+                run_start = None;
+            }
+            _ => {}
+        }
+    }
+
+    // Merge across short pauses, then drop clicks
+    let mut merged: Vec<Span> = Vec::new();
+    for span in spans {
+        match merged.last_mut() {
+            Some(last) if span.start_ms - last.end_ms < MIN_PAUSE_MS => last.end_ms = span.end_ms,
+            _ => merged.push(span),
+        }
+    }
+    merged.retain(|s| s.end_ms - s.start_ms >= MIN_SPEECH_MS);
+    merged
+}
+
+/// Relative speaking time of a word: longer words take longer, and trailing
+/// punctuation usually adds a short pause
+fn word_weight(word: &str) -> f64 {
+    let pause = if word.ends_with([',', ';', ':']) { 3.0 } else { 0.0 };
+    word.chars().filter(|c| c.is_alphanumeric()).count() as f64 + 2.0 + pause
+}
+
+/// Spread words over a span in proportion to their weights
+fn distribute(words: &[&str], span: Span, timings: &mut Vec<WordTiming>) {
+    let total: f64 = words.iter().map(|w| word_weight(w)).sum();
+    let length = (span.end_ms - span.start_ms) as f64;
+    let mut elapsed = 0.0;
+    for word in words {
+        let start = span.start_ms + (elapsed / total * length).round() as u64;
+        elapsed += word_weight(word);
+        let end = span.start_ms + (elapsed / total * length).round() as u64;
+        timings.push(WordTiming {
+            word: word.to_string(),
+            start_ms: start,
+            end_ms: end,
+        });
+    }
+}
+
+/// Group speech spans into `count` sentence spans by splitting at the
+/// longest pauses, since engines pause longest between sentences
+fn split_at_longest_pauses(spans: &[Span], count: usize) -> Vec<Span> {
+    let mut gaps: Vec<usize> = (1..spans.len()).collect();
+    gaps.sort_by_key(|&i| std::cmp::Reverse(spans[i].start_ms - spans[i - 1].end_ms));
+    let mut cuts: Vec<usize> = gaps.into_iter().take(count.saturating_sub(1)).collect();
+    cuts.sort_unstable();
+
+    let mut groups = Vec::with_capacity(count);
+    let mut first = 0;
+    for cut in cuts.into_iter().chain(std::iter::once(spans.len())) {
+        groups.push(Span {
+            start_ms: spans[first].start_ms,
+            end_ms: spans[cut - 1].end_ms,
+        });
+        first = cut;
+    }
+    groups
+}
+
+/// Word timings for `text` aligned to synthesized audio. Sentences are matched
+/// to the stretches of speech found by silence detection and words are spread
+/// within each sentence; if the pauses can't be matched up, words are spread
+/// over all of the speech. Returns one timing per whitespace-separated word.
+pub fn align_words(text: &str, samples: &[i16], channels: u16, sample_rate: u32) -> Vec<WordTiming> {
+    let sentences = split_sentences(text);
+    let sentence_words: Vec<Vec<&str>> = sentences
+        .iter()
+        .map(|s| s.split_whitespace().collect())
+        .collect();
+    let all_words: Vec<&str> = text.split_whitespace().collect();
+    let mut timings = Vec::with_capacity(all_words.len());
+
+    let spans = detect_speech(samples, channels, sample_rate);
+    let (Some(first), Some(last)) = (spans.first(), spans.last()) else {
+        // No audible speech; keep word indices with zero-length timings
+        distribute(&all_words, Span { start_ms: 0, end_ms: 0 }, &mut timings);
+        return timings;
+    };
+
+    if spans.len() >= sentence_words.len() {
+        let sentence_spans = split_at_longest_pauses(&spans, sentence_words.len());
+        for (words, span) in sentence_words.iter().zip(sentence_spans) {
+            distribute(words, span, &mut timings);
+        }
+    } else {
+        let whole = Span {
+            start_ms: first.start_ms,
+            end_ms: last.end_ms,
+        };
+        distribute(&all_words, whole, &mut timings);
+    }
+
+    timings
+}
+
+/// Align `text` to a WAV file and return the timings and the file's true duration
+pub fn align_wav(text: &str, path: &Path) -> Result<(Vec<WordTiming>, u64), TtsError> {
+    let (spec, samples) = read_wav_samples(path).map_err(|e| TtsError::new(TtsErrorKind::Io, e.message))?;
+    let frames = samples.len() as u64 / spec.channels.max(1) as u64;
+    let duration_ms = frames * 1000 / spec.sample_rate.max(1) as u64;
+    Ok((align_words(text, &samples, spec.channels, spec.sample_rate), duration_ms))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_engine::{mock_word_timings, MockBackend, MOCK_SAMPLE_RATE};
+    use crate::tts_engine::{TtsBackend, VoiceProfile};
+
+    #[test]
+    fn test_sentences_align_to_speech() {
+        let text = "First sentence here. A second, longer sentence follows it! Third?";
+        let audio = MockBackend::new()
+            .synthesize_raw(text, &VoiceProfile::default())
+            .unwrap();
+        let (expected, _) = mock_word_timings(text, &VoiceProfile::default());
+
+        let timings = align_words(text, &audio.samples, audio.channels, MOCK_SAMPLE_RATE);
+        assert_eq!(timings.len(), expected.len());
+
+        // Sentence boundaries land within one analysis frame
+        for index in [0, 3, 9] {
+            let drift = timings[index].start_ms.abs_diff(expected[index].start_ms);
+            assert!(drift <= FRAME_MS, "word {} drifted {} ms", index, drift);
+        }
+        let end_drift = timings.last().unwrap().end_ms.abs_diff(expected.last().unwrap().end_ms);
+        assert!(end_drift <= FRAME_MS);
+    }
+
+    #[test]
+    fn test_silence_keeps_word_count() {
+        let samples = vec![0i16; MOCK_SAMPLE_RATE as usize];
+        let timings = align_words("nothing was said", &samples, 1, MOCK_SAMPLE_RATE);
+        assert_eq!(timings.len(), 3);
+        assert!(timings.iter().all(|t| t.end_ms == 0));
+    }
+}
@@ -1,40 +1,150 @@
+use rodio::cpal::traits::{DeviceTrait, HostTrait};
 use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
 use std::sync::mpsc::{self, Sender, Receiver};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use crate::loudness::{LevelControl, Leveled, Loudness, LoudnessMeter, LoudnessSettings};
+use crate::navigation::{SkipUnit, TextMap};
+use crate::stretch::{StretchRate, TimeStretch, MAX_SPEED, MIN_SPEED};
+use crate::tts_engine::WordTiming;
+use crate::wav::write_wav_channels;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct AudioState {
     pub is_playing: bool,
     pub position_ms: u64,
     pub duration_ms: u64,
     pub speed: f32,
     pub volume: f32,
+    /// Streaming playback is waiting for the next chunk to be synthesized
+    pub is_buffering: bool,
+    /// Queue entry being played, if anything is loaded
+    pub segment_index: Option<usize>,
+    pub segment_count: usize,
+    /// Most recent file, device or stream that couldn't be played, cleared
+    /// once audio starts again
+    pub last_error: Option<AudioError>,
+    /// Device being played through; differs from the chosen one while it
+    /// is disconnected
+    pub output_device: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct AudioError {
+    pub kind: AudioErrorKind,
     pub message: String,
 }
 
+/// What went wrong, so the UI can suggest a fix
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AudioErrorKind {
+    /// There is no audio output device, or it couldn't be opened
+    NoOutputDevice,
+    FileNotFound,
+    /// The file exists but couldn't be read
+    Unreadable,
+    /// The file isn't audio in a supported format, or is corrupt
+    Decode,
+    /// A queue index past the end
+    InvalidSegment,
+    /// The audio thread has stopped
+    Disconnected,
+}
+
+impl AudioError {
+    pub fn new(kind: AudioErrorKind, message: impl Into<String>) -> Self {
+        AudioError {
+            kind,
+            message: message.into(),
+        }
+    }
+}
+
+/// An audio output that playback can be sent to
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutputDevice {
+    pub name: String,
+    pub is_default: bool,
+}
+
+/// How often the audio thread checks, while playing, whether its device has
+/// gone away or the chosen one has come back. Enumerating devices is slow
+/// on some hosts, so the interval doubles while no device can be opened.
+const DEVICE_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+const MAX_DEVICE_CHECK_INTERVAL: Duration = Duration::from_secs(8);
+
+/// Default interval between position events while playing
+pub const DEFAULT_TICK_MS: u64 = 50;
+
+/// Streamed audio kept in memory; chunks already played beyond this are
+/// moved to temporary files
+const MAX_STREAM_MEMORY: Duration = Duration::from_secs(600);
+
+/// Pushed from the audio thread as playback changes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PlaybackEvent {
+    /// Anything in the state other than the position changed
+    State(AudioState),
+    /// Sent on every tick while playing, and after seeks
+    Position { position_ms: u64 },
+    /// The word being spoken changed; `None` before the first word
+    Word { index: Option<usize> },
+    /// Playback moved into another queue entry
+    Segment { index: usize },
+    /// Playback reached the end of the queue
+    Ended,
+}
+
+type EventHandler = Box<dyn Fn(PlaybackEvent) + Send>;
+
+/// Where the audio thread reports the outcome of a command
+type Reply = Sender<Result<(), AudioError>>;
+
 // Commands sent to the audio thread
 #[derive(Debug)]
 pub enum AudioCommand {
-    Load { path: String, duration_ms: u64 },
+    /// Replace the queue with these files, paused at the start
+    Load(Vec<String>, Reply),
+    /// Add a file at the end of the queue
+    AppendSegment(String, Reply),
+    /// Add a file before the entry at `index`
+    InsertSegment { index: usize, path: String, reply: Reply },
+    RemoveSegment(usize, Reply),
     Play,
     Pause,
     Stop,
+    /// Move the playhead, keeping the play/pause state
+    Seek(u64, Reply),
     SetSpeed(f32),
     SetVolume(f32),
-    GetState(Sender<AudioState>),
-    IsFinished(Sender<bool>),
+    /// Interval between position events while playing, in milliseconds
+    SetTickInterval(u64),
+    /// Play through the named device, or the system default for `None`
+    SetOutputDevice(Option<String>, Reply),
+    SetLoudnessSettings(LoudnessSettings),
+    /// Measured level of the loaded document, for normalization
+    SetDocumentLoudness { loudness: Option<Loudness>, leveled: bool },
+    /// Start an empty queue that chunks are appended to as they are synthesized
+    BeginStream(Reply),
+    Append { samples: Vec<i16>, channels: u16, sample_rate: u32 },
+    /// No more chunks will be appended to the stream
+    EndStream,
 }
 
 // Thread-safe audio controller that communicates with the audio thread
+#[derive(Clone)]
 pub struct AudioController {
     command_tx: Sender<AudioCommand>,
     state: Arc<Mutex<AudioState>>,
+    /// Structure and word timings of the loaded document, for skipping
+    text_map: Arc<Mutex<TextMap>>,
+    events: Arc<Mutex<Option<EventHandler>>>,
 }
 
 // Make AudioController Send + Sync by not storing non-Send types
@@ -50,44 +160,151 @@ impl AudioController {
             duration_ms: 0,
             speed: 1.0,
             volume: 1.0,
+            is_buffering: false,
+            segment_index: None,
+            segment_count: 0,
+            last_error: None,
+            output_device: None,
         }));
 
+        let text_map = Arc::new(Mutex::new(TextMap::default()));
+        let events: Arc<Mutex<Option<EventHandler>>> = Arc::new(Mutex::new(None));
+
         let state_clone = state.clone();
+        let notifier = Notifier::new(text_map.clone(), events.clone());
 
         // Spawn audio thread
         thread::spawn(move || {
-            run_audio_thread(command_rx, state_clone);
+            run_audio_thread(command_rx, state_clone, notifier);
         });
 
-        AudioController { command_tx, state }
+        AudioController {
+            command_tx,
+            state,
+            text_map,
+            events,
+        }
     }
 
-    pub fn load(&self, path: &str, duration_ms: u64) -> Result<(), AudioError> {
-        self.command_tx
-            .send(AudioCommand::Load {
+    pub fn load(&self, path: &str) -> Result<(), AudioError> {
+        self.load_queue(vec![path.to_string()])
+    }
+
+    /// Send a command and wait for the audio thread to carry it out
+    fn request(&self, command: impl FnOnce(Reply) -> AudioCommand, name: &str) -> Result<(), AudioError> {
+        let (reply, outcome) = mpsc::channel();
+        self.command_tx.send(command(reply)).map_err(|e| {
+            AudioError::new(
+                AudioErrorKind::Disconnected,
+                format!("Failed to send {} command: {}", name, e),
+            )
+        })?;
+        outcome.recv().map_err(|_| {
+            AudioError::new(
+                AudioErrorKind::Disconnected,
+                format!("Audio thread stopped during {} command", name),
+            )
+        })?
+    }
+
+    /// Play `paths` back to back, e.g. one file per chapter. Fails if a file
+    /// can't be decoded or there is no output device.
+    pub fn load_queue(&self, paths: Vec<String>) -> Result<(), AudioError> {
+        self.request(|reply| AudioCommand::Load(paths, reply), "load")
+    }
+
+    pub fn append_segment(&self, path: &str) -> Result<(), AudioError> {
+        self.request(
+            |reply| AudioCommand::AppendSegment(path.to_string(), reply),
+            "append segment",
+        )
+    }
+
+    pub fn insert_segment(&self, index: usize, path: &str) -> Result<(), AudioError> {
+        self.request(
+            |reply| AudioCommand::InsertSegment {
+                index,
                 path: path.to_string(),
-                duration_ms,
-            })
-            .map_err(|e| AudioError {
-                message: format!("Failed to send load command: {}", e),
-            })
+                reply,
+            },
+            "insert segment",
+        )
+    }
+
+    pub fn remove_segment(&self, index: usize) -> Result<(), AudioError> {
+        self.request(|reply| AudioCommand::RemoveSegment(index, reply), "remove segment")
     }
 
     pub fn play(&self) -> Result<(), AudioError> {
-        self.command_tx.send(AudioCommand::Play).map_err(|e| AudioError {
-            message: format!("Failed to send play command: {}", e),
+        self.command_tx.send(AudioCommand::Play).map_err(|e| {
+            AudioError::new(AudioErrorKind::Disconnected, format!("Failed to send play command: {}", e))
         })
     }
 
     pub fn pause(&self) -> Result<(), AudioError> {
-        self.command_tx.send(AudioCommand::Pause).map_err(|e| AudioError {
-            message: format!("Failed to send pause command: {}", e),
+        self.command_tx.send(AudioCommand::Pause).map_err(|e| {
+            AudioError::new(AudioErrorKind::Disconnected, format!("Failed to send pause command: {}", e))
         })
     }
 
     pub fn stop(&self) -> Result<(), AudioError> {
-        self.command_tx.send(AudioCommand::Stop).map_err(|e| AudioError {
-            message: format!("Failed to send stop command: {}", e),
+        self.command_tx.send(AudioCommand::Stop).map_err(|e| {
+            AudioError::new(AudioErrorKind::Disconnected, format!("Failed to send stop command: {}", e))
+        })
+    }
+
+    /// Jump to `position_ms`, clamped to the loaded audio
+    pub fn seek(&self, position_ms: u64) -> Result<(), AudioError> {
+        self.request(|reply| AudioCommand::Seek(position_ms, reply), "seek")
+    }
+
+    /// Jump `count` units from the current position, backwards if negative,
+    /// and return the new position. Stays put if there is nowhere to go.
+    pub fn skip(&self, unit: SkipUnit, count: i32) -> Result<u64, AudioError> {
+        let state = self.get_state();
+        let target = self
+            .text_map
+            .lock()
+            .unwrap()
+            .skip_target(state.position_ms, unit, count);
+        let Some(target) = target else {
+            return Ok(state.position_ms);
+        };
+        let target = target.min(state.duration_ms);
+        self.seek(target)?;
+        Ok(target)
+    }
+
+    /// Replace the map used for skipping by sentence, paragraph or chapter
+    pub fn set_text_map(&self, text_map: TextMap) {
+        *self.text_map.lock().unwrap() = text_map;
+    }
+
+    /// Add timings for words that have just been queued for playback
+    pub fn add_word_timings(&self, timings: &[WordTiming]) {
+        self.text_map.lock().unwrap().extend_timings(timings);
+    }
+
+    pub fn begin_stream(&self) -> Result<(), AudioError> {
+        self.request(AudioCommand::BeginStream, "begin stream")
+    }
+
+    /// Queue interleaved 16-bit samples at the end of the current stream
+    pub fn append(&self, samples: Vec<i16>, channels: u16, sample_rate: u32) -> Result<(), AudioError> {
+        self.command_tx
+            .send(AudioCommand::Append {
+                samples,
+                channels,
+                sample_rate,
+            })
+            .map_err(|e| {
+                AudioError::new(AudioErrorKind::Disconnected, format!("Failed to send append command: {}", e))
+            })
+    }
+
+    pub fn end_stream(&self) -> Result<(), AudioError> {
+        self.command_tx.send(AudioCommand::EndStream).map_err(|e| {
+            AudioError::new(AudioErrorKind::Disconnected, format!("Failed to send end stream command: {}", e))
         })
     }
 
@@ -99,163 +316,919 @@ impl AudioController {
         let _ = self.command_tx.send(AudioCommand::SetVolume(volume));
     }
 
+    /// Receive playback events on the audio thread from now on
+    pub fn set_event_handler(&self, handler: impl Fn(PlaybackEvent) + Send + 'static) {
+        *self.events.lock().unwrap() = Some(Box::new(handler));
+    }
+
+    /// Move playback to another output device, keeping the position. If
+    /// the device disconnects, playback falls back to the default and
+    /// returns once it is back.
+    pub fn set_output_device(&self, name: Option<String>) -> Result<(), AudioError> {
+        self.request(|reply| AudioCommand::SetOutputDevice(name, reply), "set output device")
+    }
+
+    /// How loaded documents are normalized and compressed
+    pub fn set_loudness_settings(&self, settings: LoudnessSettings) {
+        let _ = self.command_tx.send(AudioCommand::SetLoudnessSettings(settings));
+    }
+
+    /// Normalize the loaded document from its measured level. A `leveled`
+    /// document was normalized when it was written and plays as it is.
+    /// Streams are measured as they play instead.
+    pub fn set_document_loudness(&self, loudness: Option<Loudness>, leveled: bool) {
+        let _ = self
+            .command_tx
+            .send(AudioCommand::SetDocumentLoudness { loudness, leveled });
+    }
+
+    /// How often position events are sent while playing
+    pub fn set_tick_interval(&self, interval_ms: u64) {
+        let _ = self.command_tx.send(AudioCommand::SetTickInterval(interval_ms));
+    }
+
     pub fn get_state(&self) -> AudioState {
         self.state.lock().unwrap().clone()
     }
+}
+
+/// Bits of the clock word below the generation counter
+const GENERATION_SHIFT: u32 = 48;
+const POSITION_MASK: u64 = (1 << GENERATION_SHIFT) - 1;
+/// How often a playing source publishes its position, in samples
+const CLOCK_UPDATE_SAMPLES: u64 = 64;
+
+/// Playback position in microseconds of media time, written by the sources
+/// as the output pulls samples from them. Speed changes, pauses and
+/// buffering stalls all show up as samples not being pulled, so the
+/// position can't drift from what has been played. The upper bits count
+/// generations, so sources left over from before a load or seek can't
+/// overwrite the new position.
+#[derive(Clone, Default)]
+struct PlaybackClock(Arc<AtomicU64>);
+
+impl PlaybackClock {
+    fn position(&self) -> Duration {
+        Duration::from_micros(self.0.load(Ordering::Relaxed) & POSITION_MASK)
+    }
+
+    /// Start a new generation at `position` and return it
+    fn reset(&self, position: Duration) -> u64 {
+        let generation = ((self.0.load(Ordering::SeqCst) >> GENERATION_SHIFT) + 1) & 0xffff;
+        let micros = position.as_micros() as u64 & POSITION_MASK;
+        self.0.store(generation << GENERATION_SHIFT | micros, Ordering::SeqCst);
+        generation
+    }
+
+    /// Record `position_us` if `generation` is still the current one
+    fn advance(&self, generation: u64, position_us: u64) {
+        let _ = self.0.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |word| {
+            (word >> GENERATION_SHIFT == generation)
+                .then_some(generation << GENERATION_SHIFT | (position_us & POSITION_MASK))
+        });
+    }
+}
+
+/// Counts the samples pulled from a source and publishes the matching media
+/// position to the clock. It sits outside the time stretch, which buffers
+/// input ahead of what it plays, so it counts output samples and converts
+/// them at the current speed: each one stands for `rate` samples of the
+/// recording.
+struct Tracked<S> {
+    inner: S,
+    clock: PlaybackClock,
+    generation: u64,
+    rate: StretchRate,
+    /// Media position of the source's first sample
+    origin_us: u64,
+    /// Samples pulled, counting all channels
+    samples: u64,
+    /// Samples of the recording they stand for
+    media_samples: f64,
+}
+
+impl<S: rodio::Source> Tracked<S>
+where
+    S::Item: rodio::Sample,
+{
+    fn new(inner: S, clock: &PlaybackClock, generation: u64, origin: Duration, rate: StretchRate) -> Self {
+        Tracked {
+            inner,
+            clock: clock.clone(),
+            generation,
+            rate,
+            origin_us: origin.as_micros() as u64,
+            samples: 0,
+            media_samples: 0.0,
+        }
+    }
+
+    fn per_second(&self) -> f64 {
+        self.inner.sample_rate() as f64 * self.inner.channels().max(1) as f64
+    }
+
+    fn publish(&self) {
+        let elapsed_us = (self.media_samples * 1_000_000.0 / self.per_second().max(1.0)) as u64;
+        self.clock.advance(self.generation, self.origin_us + elapsed_us);
+    }
+}
+
+impl<S: rodio::Source> Iterator for Tracked<S>
+where
+    S::Item: rodio::Sample,
+{
+    type Item = S::Item;
+
+    fn next(&mut self) -> Option<S::Item> {
+        let sample = self.inner.next();
+        if sample.is_some() {
+            self.samples += 1;
+            self.media_samples += self.rate.get() as f64;
+        }
+        if sample.is_none() || self.samples.is_multiple_of(CLOCK_UPDATE_SAMPLES) {
+            self.publish();
+        }
+        sample
+    }
+}
+
+impl<S: rodio::Source> rodio::Source for Tracked<S>
+where
+    S::Item: rodio::Sample,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), rodio::source::SeekError> {
+        self.inner.try_seek(pos)?;
+        self.media_samples = pos.as_secs_f64() * self.per_second();
+        self.publish();
+        Ok(())
+    }
+}
+
+/// Audio appended to a stream. The samples are shared with the sources
+/// playing them, so requeueing never copies them.
+#[derive(Clone)]
+struct StreamChunk {
+    channels: u16,
+    sample_rate: u32,
+    samples: Arc<[i16]>,
+}
 
-    pub fn is_finished(&self) -> bool {
-        let (tx, rx) = mpsc::channel();
-        if self.command_tx.send(AudioCommand::IsFinished(tx)).is_ok() {
-            rx.recv_timeout(Duration::from_millis(100)).unwrap_or(true)
+/// Plays a `StreamChunk`
+struct ChunkSource {
+    chunk: StreamChunk,
+    next: usize,
+}
+
+impl Iterator for ChunkSource {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        let sample = self.chunk.samples.get(self.next).copied();
+        self.next += 1;
+        sample
+    }
+}
+
+impl rodio::Source for ChunkSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        Some(self.chunk.samples.len().saturating_sub(self.next))
+    }
+
+    fn channels(&self) -> u16 {
+        self.chunk.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.chunk.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), rodio::source::SeekError> {
+        let channels = self.chunk.channels.max(1) as usize;
+        let frame = (pos.as_secs_f64() * self.chunk.sample_rate as f64) as usize;
+        self.next = (frame * channels).min(self.chunk.samples.len());
+        Ok(())
+    }
+}
+
+/// One entry in the playback queue. Entries play back to back with no gap.
+enum Segment {
+    File { path: String, duration: Duration },
+    Samples(StreamChunk),
+}
+
+type BoxedSource = Box<dyn rodio::Source<Item = i16> + Send>;
+
+fn open_decoder(path: &str) -> Result<rodio::Decoder<std::io::BufReader<std::fs::File>>, AudioError> {
+    let file = std::fs::File::open(path).map_err(|e| {
+        let kind = if e.kind() == std::io::ErrorKind::NotFound {
+            AudioErrorKind::FileNotFound
         } else {
-            true
+            AudioErrorKind::Unreadable
+        };
+        AudioError::new(kind, format!("Can't open {}: {}", path, e))
+    })?;
+    rodio::Decoder::new(std::io::BufReader::new(file))
+        .map_err(|e| AudioError::new(AudioErrorKind::Decode, format!("Can't decode {}: {}", path, e)))
+}
+
+impl Segment {
+    /// A file segment, decoded once to learn its length
+    fn file(path: String) -> Result<Self, AudioError> {
+        use rodio::Source;
+
+        let duration = open_decoder(&path)?.total_duration().ok_or_else(|| {
+            AudioError::new(AudioErrorKind::Decode, format!("Unknown duration for {}", path))
+        })?;
+        Ok(Segment::File { path, duration })
+    }
+
+    fn duration(&self) -> Duration {
+        match self {
+            Segment::File { duration, .. } => *duration,
+            Segment::Samples(chunk) => {
+                let frames = chunk.samples.len() as f64 / chunk.channels.max(1) as f64;
+                Duration::from_secs_f64(frames / chunk.sample_rate.max(1) as f64)
+            }
         }
     }
+
+    fn source(&self) -> Result<BoxedSource, AudioError> {
+        match self {
+            Segment::File { path, .. } => Ok(Box::new(open_decoder(path)?)),
+            Segment::Samples(chunk) => Ok(Box::new(ChunkSource {
+                chunk: chunk.clone(),
+                next: 0,
+            })),
+        }
+    }
+
+    /// Move streamed samples into a WAV file at `path`, keeping the
+    /// duration so the queue's timeline doesn't shift
+    fn spill(&mut self, path: &Path) -> Result<(), AudioError> {
+        let Segment::Samples(chunk) = &*self else {
+            return Ok(());
+        };
+        let duration = self.duration();
+        write_wav_channels(path, chunk.channels, chunk.sample_rate, &chunk.samples)
+            .map_err(|e| AudioError::new(AudioErrorKind::Unreadable, e.message))?;
+        *self = Segment::File {
+            path: path.to_string_lossy().to_string(),
+            duration,
+        };
+        Ok(())
+    }
+}
+
+/// The connected output devices
+pub fn list_output_devices() -> Vec<OutputDevice> {
+    let host = rodio::cpal::default_host();
+    let default = host.default_output_device().and_then(|device| device.name().ok());
+    host.output_devices()
+        .map(|devices| {
+            devices
+                .filter_map(|device| device.name().ok())
+                .map(|name| OutputDevice {
+                    is_default: default.as_ref() == Some(&name),
+                    name,
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// The device playback should use: the chosen one while it is connected,
+/// otherwise the default
+fn wanted_device(preferred: Option<&str>) -> Option<rodio::Device> {
+    let host = rodio::cpal::default_host();
+    preferred
+        .and_then(|name| {
+            host.output_devices()
+                .ok()?
+                .find(|device| device.name().is_ok_and(|n| n == name))
+        })
+        .or_else(|| host.default_output_device())
 }
 
-fn run_audio_thread(command_rx: Receiver<AudioCommand>, state: Arc<Mutex<AudioState>>) {
-    use rodio::{Decoder, OutputStream, Sink};
-    use std::fs::File;
-    use std::io::BufReader;
+/// Open an output stream on `device`, returning it with the device's name
+fn open_output(
+    device: rodio::Device,
+) -> Result<(rodio::OutputStream, rodio::OutputStreamHandle, Option<String>), AudioError> {
+    let name = device.name().ok();
+    let (stream, stream_handle) = rodio::OutputStream::try_from_device(&device).map_err(|e| {
+        AudioError::new(AudioErrorKind::NoOutputDevice, format!("Audio output error: {}", e))
+    })?;
+    Ok((stream, stream_handle, name))
+}
+
+fn no_output_device() -> AudioError {
+    AudioError::new(AudioErrorKind::NoOutputDevice, "No audio output device")
+}
+
+fn new_sink(stream_handle: &rodio::OutputStreamHandle, volume: f32) -> Result<rodio::Sink, AudioError> {
+    let sink = rodio::Sink::try_new(stream_handle)
+        .map_err(|e| AudioError::new(AudioErrorKind::NoOutputDevice, format!("Sink error: {}", e)))?;
+    sink.set_volume(volume);
+    sink.pause();
+    Ok(sink)
+}
 
-    let mut sink: Option<Sink> = None;
-    let mut _stream: Option<OutputStream> = None;
-    let mut start_time: Option<Instant> = None;
-    let mut pause_position = Duration::ZERO;
-    let mut duration = Duration::ZERO;
-    let mut speed = 1.0f32;
-    let mut volume = 1.0f32;
-    let mut is_playing = false;
+/// Processing applied to every queued source, with settings shared with
+/// the sources so changes reach audio already in the sink
+#[derive(Clone, Default)]
+struct Effects {
+    rate: StretchRate,
+    level: LevelControl,
+}
+
+impl Effects {
+    /// Stretch and level `source`, reporting its position to `clock` as
+    /// media time from `origin`
+    fn apply<S>(
+        &self,
+        source: S,
+        clock: &PlaybackClock,
+        generation: u64,
+        origin: Duration,
+    ) -> Leveled<Tracked<TimeStretch<S>>>
+    where
+        S: rodio::Source<Item = i16>,
+    {
+        let stretched = TimeStretch::new(source, self.rate.clone());
+        let tracked = Tracked::new(stretched, clock, generation, origin, self.rate.clone());
+        Leveled::new(tracked, self.level.clone())
+    }
+}
+
+/// Queue `segments` on `sink` starting `position` into them, restarting the
+/// clock there. Returns the clock generation the queued sources report in.
+fn queue_from(
+    sink: &rodio::Sink,
+    segments: &[Segment],
+    position: Duration,
+    clock: &PlaybackClock,
+    effects: &Effects,
+) -> Result<u64, AudioError> {
+    use rodio::Source;
+
+    let generation = clock.reset(position);
+    let mut start = Duration::ZERO;
+    for segment in segments {
+        let end = start + segment.duration();
+        if end > position {
+            let mut source = effects.apply(segment.source()?, clock, generation, start);
+            if position > start {
+                source
+                    .try_seek(position - start)
+                    .map_err(|e| AudioError::new(AudioErrorKind::Decode, format!("Seek error: {}", e)))?;
+            }
+            sink.append(source);
+        }
+        start = end;
+    }
+    Ok(generation)
+}
+
+/// Playback state owned by the audio thread
+struct Player {
+    sink: Option<rodio::Sink>,
+    _stream: Option<rodio::OutputStream>,
+    stream_handle: Option<rodio::OutputStreamHandle>,
+    queue: Vec<Segment>,
+    clock: PlaybackClock,
+    /// Clock generation of the sources in the current sink
+    generation: u64,
+    effects: Effects,
+    loudness: LoudnessSettings,
+    /// Level of the loaded document, if known
+    document: Option<Loudness>,
+    /// The document's file already has the loudness settings applied
+    document_leveled: bool,
+    /// Measures a stream as its chunks arrive
+    meter: Option<LoudnessMeter>,
+    volume: f32,
+    is_playing: bool,
+    /// More segments are expected from a stream
+    stream_open: bool,
+    is_buffering: bool,
+    last_error: Option<AudioError>,
+    /// Device chosen by the user; `None` follows the system default
+    device: Option<String>,
+    /// Device the open stream plays through
+    device_in_use: Option<String>,
+    last_device_check: Instant,
+    device_check_interval: Duration,
+    /// Temporary files holding played stream chunks
+    spilled: Vec<PathBuf>,
+}
+
+impl Player {
+    fn new() -> Self {
+        Player {
+            sink: None,
+            _stream: None,
+            stream_handle: None,
+            queue: Vec::new(),
+            clock: PlaybackClock::default(),
+            generation: 0,
+            effects: Effects::default(),
+            loudness: LoudnessSettings::default(),
+            document: None,
+            document_leveled: false,
+            meter: None,
+            volume: 1.0,
+            is_playing: false,
+            stream_open: false,
+            is_buffering: false,
+            last_error: None,
+            device: None,
+            device_in_use: None,
+            last_device_check: Instant::now(),
+            device_check_interval: DEVICE_CHECK_INTERVAL,
+            spilled: Vec::new(),
+        }
+    }
+
+    fn duration(&self) -> Duration {
+        self.queue.iter().map(Segment::duration).sum()
+    }
+
+    /// Index of the segment playing at `position`
+    fn segment_at(&self, position: Duration) -> Option<usize> {
+        let mut start = Duration::ZERO;
+        for (index, segment) in self.queue.iter().enumerate() {
+            start += segment.duration();
+            if position < start {
+                return Some(index);
+            }
+        }
+        self.queue.len().checked_sub(1)
+    }
+
+    fn segment_start(&self, index: usize) -> Duration {
+        self.queue[..index].iter().map(Segment::duration).sum()
+    }
+
+    fn stop(&mut self) {
+        if let Some(sink) = self.sink.take() {
+            sink.stop();
+        }
+        self._stream = None;
+        self.stream_handle = None;
+        self.device_in_use = None;
+        self.queue.clear();
+        self.generation = self.clock.reset(Duration::ZERO);
+        self.is_playing = false;
+        self.stream_open = false;
+        self.is_buffering = false;
+        for path in self.spilled.drain(..) {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    /// Replace whatever was playing with `queue`, paused at its start
+    fn load(&mut self, queue: Vec<Segment>, stream_open: bool) -> Result<(), AudioError> {
+        self.stop();
+        let device = wanted_device(self.device.as_deref()).ok_or_else(no_output_device)?;
+        let (stream, stream_handle, device_in_use) = open_output(device)?;
+        let sink = new_sink(&stream_handle, self.volume)?;
+        self.generation = queue_from(&sink, &queue, Duration::ZERO, &self.clock, &self.effects)?;
+        self.sink = Some(sink);
+        self._stream = Some(stream);
+        self.stream_handle = Some(stream_handle);
+        self.device_in_use = device_in_use;
+        self.queue = queue;
+        self.stream_open = stream_open;
+        self.last_error = None;
+        self.document = None;
+        self.document_leveled = false;
+        self.meter = None;
+        self.apply_level();
+        Ok(())
+    }
+
+    /// Requeue everything from `position` on a fresh sink, keeping the
+    /// play/pause state. The sink itself can only seek within its current
+    /// source, and not into sources it has finished.
+    fn rebuild(&mut self, position: Duration) -> Result<(), AudioError> {
+        let Some(stream_handle) = &self.stream_handle else {
+            return Ok(());
+        };
+        let position = position.min(self.duration());
+        let sink = new_sink(stream_handle, self.volume)?;
+        self.generation = queue_from(&sink, &self.queue, position, &self.clock, &self.effects)?;
+        if self.is_playing {
+            sink.play();
+        }
+        if let Some(old) = self.sink.replace(sink) {
+            old.stop();
+        }
+        Ok(())
+    }
+
+    fn apply_level(&self) {
+        if self.document_leveled {
+            self.effects.level.set(1.0, false);
+            return;
+        }
+        let gain = self.loudness.gain(self.document.as_ref());
+        self.effects.level.set(gain, self.loudness.compressor);
+    }
+
+    /// Move whatever is loaded onto `device`, keeping the position
+    fn switch_output(&mut self, device: rodio::Device) -> Result<(), AudioError> {
+        if self.stream_handle.is_none() {
+            return Ok(());
+        }
+        let position = self.clock.position();
+        let (stream, stream_handle, device_in_use) = open_output(device)?;
+        self.stream_handle = Some(stream_handle);
+        self.device_in_use = device_in_use;
+        self.rebuild(position)?;
+        // Closed only now, after its sink has stopped
+        self._stream = Some(stream);
+        Ok(())
+    }
+
+    /// Follow the user's device choice, falling back to the default while
+    /// the chosen device is disconnected
+    fn set_device(&mut self, device: Option<String>) -> Result<(), AudioError> {
+        self.device = device;
+        if self.stream_handle.is_none() {
+            return Ok(());
+        }
+        let wanted = wanted_device(self.device.as_deref()).ok_or_else(no_output_device)?;
+        self.switch_output(wanted)
+    }
+
+    /// While playing, move to the wanted device if the one in use isn't
+    /// it, e.g. because it was unplugged or the chosen one is back
+    fn check_device(&mut self) -> Result<(), AudioError> {
+        if self.stream_handle.is_none()
+            || !self.is_playing
+            || self.last_device_check.elapsed() < self.device_check_interval
+        {
+            return Ok(());
+        }
+        self.last_device_check = Instant::now();
+        let result = match wanted_device(self.device.as_deref()) {
+            Some(wanted) => match wanted.name() {
+                Ok(name) if Some(&name) != self.device_in_use.as_ref() => self.switch_output(wanted),
+                _ => Ok(()),
+            },
+            None => Err(no_output_device()),
+        };
+        // A device that plays is checked often, so losing it is noticed
+        // quickly; back off only while none can be opened
+        self.device_check_interval = match result {
+            Ok(()) => DEVICE_CHECK_INTERVAL,
+            Err(_) => (self.device_check_interval * 2).min(MAX_DEVICE_CHECK_INTERVAL),
+        };
+        result
+    }
+
+    /// Add a segment at the end of the queue, straight onto the sink so
+    /// playback runs into it without a gap
+    fn append(&mut self, segment: Segment) -> Result<(), AudioError> {
+        if let Segment::Samples(chunk) = &segment {
+            let meter = self
+                .meter
+                .get_or_insert_with(|| LoudnessMeter::new(chunk.channels, chunk.sample_rate));
+            meter.add(&chunk.samples);
+            self.document = meter.loudness();
+            self.apply_level();
+        }
+        if let Some(sink) = &self.sink {
+            let start = self.duration();
+            sink.append(self.effects.apply(segment.source()?, &self.clock, self.generation, start));
+        }
+        self.queue.push(segment);
+        self.is_buffering = false;
+        self.spill_played(MAX_STREAM_MEMORY)
+    }
+
+    /// Move the oldest stream chunks the playhead has passed into
+    /// temporary files while more than `keep` of streamed audio is in memory
+    fn spill_played(&mut self, keep: Duration) -> Result<(), AudioError> {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+        let in_memory = |queue: &[Segment]| -> Duration {
+            queue
+                .iter()
+                .filter(|segment| matches!(segment, Segment::Samples(_)))
+                .map(Segment::duration)
+                .sum()
+        };
+        let playing = self.segment_at(self.clock.position()).unwrap_or(0);
+        let mut index = 0;
+        while index < playing && in_memory(&self.queue) > keep {
+            if matches!(self.queue[index], Segment::Samples(_)) {
+                let path = std::env::temp_dir().join(format!(
+                    "pdf_audiobook_stream_{}_{}.wav",
+                    std::process::id(),
+                    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+                ));
+                self.spilled.push(path.clone());
+                self.queue[index].spill(&path)?;
+            }
+            index += 1;
+        }
+        Ok(())
+    }
+
+    fn insert(&mut self, index: usize, segment: Segment) -> Result<(), AudioError> {
+        if index >= self.queue.len() {
+            return self.append(segment);
+        }
+        let mut position = self.clock.position();
+        // Inserting at or before the playing segment pushes it later
+        if self.segment_at(position).is_some_and(|current| index <= current) {
+            position += segment.duration();
+        }
+        self.queue.insert(index, segment);
+        self.rebuild(position)
+    }
+
+    fn remove(&mut self, index: usize) -> Result<(), AudioError> {
+        if index >= self.queue.len() {
+            return Err(AudioError::new(
+                AudioErrorKind::InvalidSegment,
+                format!("No segment {} in a queue of {}", index, self.queue.len()),
+            ));
+        }
+        let position = self.clock.position();
+        let start = self.segment_start(index);
+        let removed = self.queue[index].duration();
+        // Removing the playing segment continues with the next one
+        let position = match self.segment_at(position) {
+            Some(current) if index < current => position.saturating_sub(removed),
+            Some(current) if index == current => start,
+            _ => position,
+        };
+        self.queue.remove(index);
+        self.rebuild(position)
+    }
+
+    fn snapshot(&self) -> AudioState {
+        let position = self.clock.position();
+        AudioState {
+            is_playing: self.is_playing,
+            position_ms: position.as_millis() as u64,
+            duration_ms: self.duration().as_millis() as u64,
+            speed: self.effects.rate.get(),
+            volume: self.volume,
+            is_buffering: self.is_buffering,
+            segment_index: self.segment_at(position),
+            segment_count: self.queue.len(),
+            last_error: self.last_error.clone(),
+            output_device: self.device_in_use.clone(),
+        }
+    }
+}
+
+impl Drop for Player {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Turns successive snapshots of the player into playback events
+struct Notifier {
+    text_map: Arc<Mutex<TextMap>>,
+    events: Arc<Mutex<Option<EventHandler>>>,
+    tick: Duration,
+    last_tick: Instant,
+    last_state: Option<AudioState>,
+    last_position_ms: Option<u64>,
+    last_word: Option<usize>,
+}
+
+impl Notifier {
+    fn new(text_map: Arc<Mutex<TextMap>>, events: Arc<Mutex<Option<EventHandler>>>) -> Self {
+        Notifier {
+            text_map,
+            events,
+            tick: Duration::from_millis(DEFAULT_TICK_MS),
+            last_tick: Instant::now(),
+            last_state: None,
+            last_position_ms: None,
+            last_word: None,
+        }
+    }
+
+    fn emit(&self, event: PlaybackEvent) {
+        if let Some(handler) = self.events.lock().unwrap().as_ref() {
+            handler(event);
+        }
+    }
+
+    /// Send whatever changed since the last snapshot. Position events are
+    /// rate limited to the tick interval unless `immediate`, e.g. after a
+    /// command moved the playhead.
+    fn update(&mut self, state: &AudioState, immediate: bool) {
+        let without_position = AudioState {
+            position_ms: 0,
+            ..state.clone()
+        };
+        let previous = self.last_state.replace(without_position.clone());
+        if previous.as_ref() != Some(&without_position) {
+            self.emit(PlaybackEvent::State(state.clone()));
+        }
+
+        if let Some(index) = state.segment_index {
+            if previous.and_then(|s| s.segment_index) != Some(index) {
+                self.emit(PlaybackEvent::Segment { index });
+            }
+        }
+
+        if self.last_position_ms != Some(state.position_ms)
+            && (immediate || self.last_tick.elapsed() >= self.tick)
+        {
+            self.last_tick = Instant::now();
+            self.last_position_ms = Some(state.position_ms);
+            self.emit(PlaybackEvent::Position {
+                position_ms: state.position_ms,
+            });
+        }
+
+        let word = self.text_map.lock().unwrap().word_at(state.position_ms);
+        if word != self.last_word {
+            self.last_word = word;
+            self.emit(PlaybackEvent::Word { index: word });
+        }
+    }
+}
+
+/// Report a command's outcome to the caller waiting for it, and pass it
+/// on so a file or device that can't be played is kept for the UI too
+fn answer(reply: Reply, result: Result<(), AudioError>) -> Option<Result<(), AudioError>> {
+    let _ = reply.send(result.clone());
+    Some(result)
+}
+
+/// Only a failure of a command that doesn't itself start audio is kept;
+/// its success says nothing about an earlier failure
+fn failure(outcome: Option<Result<(), AudioError>>) -> Option<Result<(), AudioError>> {
+    outcome.filter(Result::is_err)
+}
+
+fn run_audio_thread(
+    command_rx: Receiver<AudioCommand>,
+    state: Arc<Mutex<AudioState>>,
+    mut notifier: Notifier,
+) {
+    let mut player = Player::new();
 
     loop {
-        // Process commands with timeout to allow state updates
-        match command_rx.recv_timeout(Duration::from_millis(50)) {
-            Ok(cmd) => match cmd {
-                AudioCommand::Load { path, duration_ms } => {
-                    // Stop any existing playback
-                    if let Some(s) = sink.take() {
-                        s.stop();
+        let mut commanded = false;
+        // Wake at least once per tick to report the position. `outcome` is
+        // set when the thread started audio or failed to; anything else
+        // leaves the last error as it was
+        let outcome = match command_rx.recv_timeout(notifier.tick) {
+            Ok(cmd) => {
+                commanded = true;
+                match cmd {
+                    AudioCommand::Load(paths, reply) => answer(
+                        reply,
+                        paths
+                            .into_iter()
+                            .map(Segment::file)
+                            .collect::<Result<Vec<_>, _>>()
+                            .and_then(|queue| player.load(queue, false)),
+                    ),
+                    AudioCommand::AppendSegment(path, reply) => failure(answer(
+                        reply,
+                        Segment::file(path).and_then(|segment| player.append(segment)),
+                    )),
+                    AudioCommand::InsertSegment { index, path, reply } => failure(answer(
+                        reply,
+                        Segment::file(path).and_then(|segment| player.insert(index, segment)),
+                    )),
+                    AudioCommand::RemoveSegment(index, reply) => {
+                        // A bad index is the caller's mistake, not a playback failure
+                        let _ = reply.send(player.remove(index));
+                        None
                     }
-
-                    // Create new audio output
-                    match OutputStream::try_default() {
-                        Ok((stream, stream_handle)) => {
-                            match File::open(&path) {
-                                Ok(file) => {
-                                    let reader = BufReader::new(file);
-                                    match Decoder::new(reader) {
-                                        Ok(source) => {
-                                            match Sink::try_new(&stream_handle) {
-                                                Ok(new_sink) => {
-                                                    new_sink.set_speed(speed);
-                                                    new_sink.set_volume(volume);
-                                                    new_sink.append(source);
-                                                    new_sink.pause();
-                                                    sink = Some(new_sink);
-                                                    _stream = Some(stream);
-                                                    duration = Duration::from_millis(duration_ms);
-                                                    pause_position = Duration::ZERO;
-                                                    start_time = None;
-                                                    is_playing = false;
-                                                }
-                                                Err(e) => eprintln!("Sink error: {}", e),
-                                            }
-                                        }
-                                        Err(e) => eprintln!("Decoder error: {}", e),
-                                    }
-                                }
-                                Err(e) => eprintln!("File open error: {}", e),
-                            }
+                    AudioCommand::Play => match player.sink {
+                        Some(ref s) => {
+                            s.play();
+                            player.is_playing = true;
+                            // The device may have changed while paused
+                            player.device_check_interval = DEVICE_CHECK_INTERVAL;
+                            Some(Ok(()))
                         }
-                        Err(e) => eprintln!("Audio output error: {}", e),
+                        None => None,
+                    },
+                    AudioCommand::Pause => {
+                        if let Some(ref s) = player.sink {
+                            s.pause();
+                            player.is_playing = false;
+                        }
+                        None
                     }
-                }
-                AudioCommand::Play => {
-                    if let Some(ref s) = sink {
-                        s.play();
-                        start_time = Some(Instant::now());
-                        is_playing = true;
+                    AudioCommand::Stop => {
+                        player.stop();
+                        None
                     }
-                }
-                AudioCommand::Pause => {
-                    if let Some(ref s) = sink {
-                        s.pause();
-                        if let Some(start) = start_time.take() {
-                            pause_position += start.elapsed();
-                        }
-                        is_playing = false;
+                    AudioCommand::Seek(position_ms, reply) => {
+                        answer(reply, player.rebuild(Duration::from_millis(position_ms)))
                     }
-                }
-                AudioCommand::Stop => {
-                    if let Some(s) = sink.take() {
-                        s.stop();
+                    AudioCommand::BeginStream(reply) => answer(reply, player.load(Vec::new(), true)),
+                    AudioCommand::Append {
+                        samples,
+                        channels,
+                        sample_rate,
+                    } => Some(player.append(Segment::Samples(StreamChunk {
+                        channels,
+                        sample_rate,
+                        samples: samples.into(),
+                    }))),
+                    AudioCommand::EndStream => {
+                        player.stream_open = false;
+                        None
                     }
-                    _stream = None;
-                    start_time = None;
-                    pause_position = Duration::ZERO;
-                    is_playing = false;
-                }
-                AudioCommand::SetSpeed(s) => {
-                    speed = s.clamp(0.5, 2.0);
-                    if let Some(ref sink) = sink {
-                        sink.set_speed(speed);
+                    AudioCommand::SetSpeed(s) => {
+                        player.effects.rate.set(s.clamp(MIN_SPEED, MAX_SPEED));
+                        None
                     }
-                }
-                AudioCommand::SetVolume(v) => {
-                    volume = v.clamp(0.0, 1.0);
-                    if let Some(ref sink) = sink {
-                        sink.set_volume(volume);
+                    AudioCommand::SetVolume(v) => {
+                        player.volume = v.clamp(0.0, 1.0);
+                        if let Some(ref sink) = player.sink {
+                            sink.set_volume(player.volume);
+                        }
+                        None
                     }
-                }
-                AudioCommand::GetState(tx) => {
-                    let position = if let Some(start) = start_time {
-                        pause_position + start.elapsed()
-                    } else {
-                        pause_position
-                    };
-                    let position_ms = (position.as_secs_f64() * speed as f64 * 1000.0) as u64;
-
-                    let _ = tx.send(AudioState {
-                        is_playing,
-                        position_ms,
-                        duration_ms: duration.as_millis() as u64,
-                        speed,
-                        volume,
-                    });
-                }
-                AudioCommand::IsFinished(tx) => {
-                    let finished = sink.as_ref().map(|s| s.empty()).unwrap_or(true);
-                    let _ = tx.send(finished);
-                }
-            },
-            Err(mpsc::RecvTimeoutError::Timeout) => {
-                // Update state periodically
-                let position = if let Some(start) = start_time {
-                    pause_position + start.elapsed()
-                } else {
-                    pause_position
-                };
-                let position_ms = (position.as_secs_f64() * speed as f64 * 1000.0) as u64;
-
-                if let Ok(mut s) = state.lock() {
-                    s.is_playing = is_playing;
-                    s.position_ms = position_ms;
-                    s.duration_ms = duration.as_millis() as u64;
-                    s.speed = speed;
-                    s.volume = volume;
-                }
-
-                // Check if finished
-                if let Some(ref s) = sink {
-                    if s.empty() && is_playing {
-                        is_playing = false;
+                    AudioCommand::SetOutputDevice(device, reply) => answer(reply, player.set_device(device)),
+                    AudioCommand::SetLoudnessSettings(settings) => {
+                        player.loudness = settings;
+                        player.apply_level();
+                        None
+                    }
+                    AudioCommand::SetDocumentLoudness { loudness, leveled } => {
+                        player.document = loudness;
+                        player.document_leveled = leveled;
+                        player.apply_level();
+                        None
+                    }
+                    AudioCommand::SetTickInterval(ms) => {
+                        notifier.tick = Duration::from_millis(ms.max(1));
+                        None
                     }
                 }
             }
+            Err(mpsc::RecvTimeoutError::Timeout) => None,
             Err(mpsc::RecvTimeoutError::Disconnected) => {
                 // Controller dropped, exit thread
                 break;
             }
+        };
+        // Keep a playback failure until audio starts again
+        match outcome {
+            Some(Ok(())) => player.last_error = None,
+            Some(Err(e)) => player.last_error = Some(e),
+            None => {}
+        }
+        if let Err(e) = player.check_device() {
+            player.last_error = Some(e);
+        }
+        // Check if finished, or stalled waiting for the stream
+        let mut ended = false;
+        if let Some(ref s) = player.sink {
+            if s.empty() && player.is_playing {
+                if player.stream_open {
+                    player.is_buffering = true;
+                } else {
+                    player.is_playing = false;
+                    ended = true;
+                }
+            }
+        }
+        let snapshot = player.snapshot();
+        if let Ok(mut s) = state.lock() {
+            *s = snapshot.clone();
+        }
+        notifier.update(&snapshot, commanded || ended);
+        if ended {
+            notifier.emit(PlaybackEvent::Ended);
         }
     }
 }
@@ -263,3 +1236,149 @@ fn run_audio_thread(command_rx: Receiver<AudioCommand>, state: Arc<Mutex<AudioSt
 pub fn create_audio_controller() -> AudioController {
     AudioController::new()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+    use rodio::Source;
+
+    #[test]
+    fn test_clock_follows_consumed_samples() {
+        let clock = PlaybackClock::default();
+        let generation = clock.reset(Duration::ZERO);
+        // One second of stereo at 1 kHz, starting 5 s into the media
+        let buffer = SamplesBuffer::new(2, 1000, vec![0i16; 2000]);
+        let mut source = Tracked::new(buffer, &clock, generation, Duration::from_secs(5), StretchRate::default());
+
+        for _ in 0..1024 {
+            source.next();
+        }
+        assert_eq!(clock.position().as_millis(), 5512);
+        source.try_seek(Duration::from_millis(250)).unwrap();
+        assert_eq!(clock.position().as_millis(), 5250);
+
+        // A source from before a seek no longer moves the clock
+        clock.reset(Duration::from_secs(1));
+        while source.next().is_some() {}
+        assert_eq!(clock.position().as_millis(), 1000);
+    }
+
+    #[test]
+    fn test_clock_follows_stretched_output() {
+        let clock = PlaybackClock::default();
+        let generation = clock.reset(Duration::ZERO);
+        let effects = Effects::default();
+        effects.rate.set(2.0);
+        // One second of a 16 kHz tone, played at double speed
+        let tone = (0..16000).map(|i| ((i as f32 * 0.17).sin() * 8000.0) as i16).collect::<Vec<_>>();
+        let buffer = SamplesBuffer::new(1, 16000, tone);
+        let mut source = effects.apply(buffer, &clock, generation, Duration::ZERO);
+
+        // A quarter second of output is half a second of the recording,
+        // however far ahead the stretch has read
+        for _ in 0..4000 {
+            source.next();
+        }
+        let position = clock.position().as_millis() as i64;
+        assert!((position - 500).abs() <= 5, "position was {}ms", position);
+    }
+
+    #[test]
+    fn test_unplayable_files_are_classified() {
+        let missing = std::env::temp_dir().join("audio_test_missing.wav");
+        let error = Segment::file(missing.to_string_lossy().into_owned()).err().unwrap();
+        assert_eq!(error.kind, AudioErrorKind::FileNotFound);
+
+        let not_audio = std::env::temp_dir().join(format!("audio_test_{}.wav", std::process::id()));
+        std::fs::write(&not_audio, "not audio").unwrap();
+        let error = Segment::file(not_audio.to_string_lossy().into_owned()).err().unwrap();
+        let _ = std::fs::remove_file(&not_audio);
+        assert_eq!(error.kind, AudioErrorKind::Decode);
+    }
+
+    #[test]
+    fn test_notifier_sends_changes() {
+        let timings: Vec<WordTiming> = (0..3)
+            .map(|i| WordTiming {
+                word: "word".to_string(),
+                start_ms: i * 1000,
+                end_ms: i * 1000 + 900,
+            })
+            .collect();
+        let text_map = TextMap::new(&["word word word".to_string()], timings);
+        let events: Arc<Mutex<Option<EventHandler>>> = Arc::new(Mutex::new(None));
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let log = sent.clone();
+        *events.lock().unwrap() = Some(Box::new(move |event| log.lock().unwrap().push(event)));
+        let mut notifier = Notifier::new(Arc::new(Mutex::new(text_map)), events);
+        notifier.tick = Duration::from_secs(60);
+
+        let mut state = AudioState {
+            is_playing: true,
+            position_ms: 0,
+            duration_ms: 3000,
+            speed: 1.0,
+            volume: 1.0,
+            is_buffering: false,
+            segment_index: Some(0),
+            segment_count: 1,
+            last_error: None,
+            output_device: None,
+        };
+        notifier.update(&state, true);
+        // Within the tick only the word change is sent
+        state.position_ms = 1200;
+        notifier.update(&state, false);
+        state.is_playing = false;
+        notifier.update(&state, false);
+
+        let kinds: Vec<String> = sent
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|event| serde_json::to_value(event).unwrap()["type"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(kinds, ["state", "segment", "position", "word", "word", "state"]);
+    }
+
+    #[test]
+    fn test_spilled_chunk_plays_the_same() {
+        let samples: Vec<i16> = (0..1000).map(|i| i as i16).collect();
+        let mut segment = Segment::Samples(StreamChunk {
+            channels: 1,
+            sample_rate: 1000,
+            samples: samples.clone().into(),
+        });
+        let path = std::env::temp_dir().join(format!("audio_test_spill_{}.wav", std::process::id()));
+        segment.spill(&path).unwrap();
+        let played: Vec<i16> = segment.source().unwrap().collect();
+        let _ = std::fs::remove_file(&path);
+
+        assert!(matches!(segment, Segment::File { .. }));
+        assert_eq!(segment.duration(), Duration::from_secs(1));
+        assert_eq!(played, samples);
+    }
+
+    #[test]
+    fn test_queue_plays_across_segments() {
+        let second = || {
+            Segment::Samples(StreamChunk {
+                channels: 1,
+                sample_rate: 1000,
+                samples: vec![0; 1000].into(),
+            })
+        };
+        let queue = vec![second(), second()];
+        let clock = PlaybackClock::default();
+        let (sink, mut output) = rodio::Sink::new_idle();
+
+        // Starting halfway through the first segment plays into the second
+        queue_from(&sink, &queue, Duration::from_millis(500), &clock, &Effects::default()).unwrap();
+        for _ in 0..1000 {
+            output.next();
+        }
+        let position = clock.position().as_millis();
+        assert!((1400..=1500).contains(&position), "position {}", position);
+    }
+}
@@ -0,0 +1,424 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use crate::tts_engine::{
+    BackendKind, RawAudio, TtsBackend, TtsError, TtsResult, VoiceInfo, VoiceProfile, WordTiming,
+};
+use crate::wav::{read_wav_samples, write_wav_channels};
+
+/// Bumped whenever cached audio or timings would be produced differently
+const CACHE_VERSION: u32 = 1;
+/// Default size limit for cached audio
+pub const DEFAULT_CACHE_MAX_MB: u64 = 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheError {
+    pub message: String,
+}
+
+/// Disk usage of the synthesis cache
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheUsage {
+    pub path: String,
+    pub entries: usize,
+    pub bytes: u64,
+    pub max_bytes: u64,
+    /// Why the most recent chunk couldn't be stored, if it couldn't
+    pub last_error: Option<String>,
+}
+
+/// Timings stored next to each cached WAV
+#[derive(Serialize, Deserialize)]
+struct CachedTimings {
+    word_timings: Vec<WordTiming>,
+    duration_ms: u64,
+}
+
+/// A cached WAV and its timings sidecar
+struct CacheEntry {
+    wav: PathBuf,
+    timings: PathBuf,
+    bytes: u64,
+    used: SystemTime,
+}
+
+/// Synthesized chunks stored on disk under a hash of everything that
+/// affects the audio, evicting the least recently used past a size limit
+pub struct SynthesisCache {
+    dir: PathBuf,
+    max_bytes: AtomicU64,
+    /// Serializes eviction and clearing against each other
+    lock: Mutex<()>,
+    last_error: Mutex<Option<String>>,
+}
+
+/// Cache key for `text` spoken by `backend` with `profile`, where `engine`
+/// is the executable and model the profile resolved to. Whitespace is
+/// normalized since it doesn't change what gets spoken.
+pub fn cache_key(backend: BackendKind, engine: &str, profile: &VoiceProfile, text: &str) -> String {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    let material = serde_json::json!({
+        "version": CACHE_VERSION,
+        "backend": backend,
+        "engine": engine,
+        "profile": profile,
+        "text": text,
+    });
+
+    Sha256::digest(material.to_string().as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn io_error(action: &str, path: &Path, e: std::io::Error) -> CacheError {
+    CacheError {
+        message: format!("Failed to {} {}: {}", action, path.display(), e),
+    }
+}
+
+impl SynthesisCache {
+    pub fn new(dir: PathBuf, max_bytes: u64) -> Self {
+        SynthesisCache {
+            dir,
+            max_bytes: AtomicU64::new(max_bytes),
+            lock: Mutex::new(()),
+            last_error: Mutex::new(None),
+        }
+    }
+
+    pub fn set_max_bytes(&self, max_bytes: u64) {
+        self.max_bytes.store(max_bytes, Ordering::Relaxed);
+        let _ = self.evict();
+    }
+
+    fn wav_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.wav", key))
+    }
+
+    fn timings_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+
+    fn read_timings(&self, key: &str) -> Option<CachedTimings> {
+        serde_json::from_str(&fs::read_to_string(self.timings_path(key)).ok()?).ok()
+    }
+
+    /// The WAV's modification time doubles as its last-used time
+    fn touch(&self, key: &str) {
+        if let Ok(file) = fs::File::options().append(true).open(self.wav_path(key)) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+    }
+
+    /// Copy a cached chunk to `output_path`, marking it as recently used
+    pub fn fetch(&self, key: &str, output_path: &Path) -> Option<TtsResult> {
+        let timings = self.read_timings(key)?;
+        fs::copy(self.wav_path(key), output_path).ok()?;
+        self.touch(key);
+
+        Some(TtsResult {
+            audio_path: output_path.to_string_lossy().to_string(),
+            word_timings: timings.word_timings,
+            duration_ms: timings.duration_ms,
+        })
+    }
+
+    /// Read a cached chunk into memory, marking it as recently used
+    pub fn fetch_raw(&self, key: &str) -> Option<RawAudio> {
+        let timings = self.read_timings(key)?;
+        let (spec, samples) = read_wav_samples(&self.wav_path(key)).ok()?;
+        self.touch(key);
+
+        Some(RawAudio {
+            sample_rate: spec.sample_rate,
+            channels: spec.channels,
+            samples,
+            word_timings: timings.word_timings,
+        })
+    }
+
+    /// Store a freshly synthesized chunk, then evict old entries if the
+    /// cache is over its limit
+    pub fn store(&self, key: &str, result: &TtsResult) -> Result<(), CacheError> {
+        let timings = CachedTimings {
+            word_timings: result.word_timings.clone(),
+            duration_ms: result.duration_ms,
+        };
+        self.store_with(key, &timings, |wav_tmp| {
+            fs::copy(&result.audio_path, wav_tmp)
+                .map(|_| ())
+                .map_err(|e| io_error("write", wav_tmp, e))
+        })
+    }
+
+    /// Store a chunk synthesized into memory, as `store` does
+    pub fn store_raw(&self, key: &str, audio: &RawAudio) -> Result<(), CacheError> {
+        let timings = CachedTimings {
+            word_timings: audio.word_timings.clone(),
+            duration_ms: audio.duration_ms().round() as u64,
+        };
+        self.store_with(key, &timings, |wav_tmp| {
+            write_wav_channels(wav_tmp, audio.channels, audio.sample_rate, &audio.samples)
+                .map_err(|e| CacheError { message: e.message })
+        })
+    }
+
+    fn store_with(
+        &self,
+        key: &str,
+        timings: &CachedTimings,
+        write_wav: impl FnOnce(&Path) -> Result<(), CacheError>,
+    ) -> Result<(), CacheError> {
+        fs::create_dir_all(&self.dir).map_err(|e| io_error("create", &self.dir, e))?;
+
+        // Write under temporary names and rename, so concurrent readers
+        // never see a partial entry. The sidecar goes first because a WAV
+        // without timings is ignored.
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        let suffix = format!("{}.{}.tmp", std::process::id(), NEXT_ID.fetch_add(1, Ordering::Relaxed));
+        let timings_tmp = self.dir.join(format!("{}.json.{}", key, suffix));
+        let wav_tmp = self.dir.join(format!("{}.wav.{}", key, suffix));
+
+        let contents = serde_json::to_string(timings).map_err(|e| CacheError {
+            message: format!("Failed to serialize timings: {}", e),
+        })?;
+        fs::write(&timings_tmp, contents).map_err(|e| io_error("write", &timings_tmp, e))?;
+        fs::rename(&timings_tmp, self.timings_path(key))
+            .map_err(|e| io_error("write", &self.timings_path(key), e))?;
+
+        write_wav(&wav_tmp)?;
+        fs::rename(&wav_tmp, self.wav_path(key))
+            .map_err(|e| io_error("write", &self.wav_path(key), e))?;
+
+        self.evict()
+    }
+
+    fn entries(&self) -> Vec<CacheEntry> {
+        let Ok(dir) = fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+
+        dir.filter_map(Result::ok)
+            .filter_map(|file| {
+                let wav = file.path();
+                if wav.extension()? != "wav" {
+                    return None;
+                }
+                let timings = wav.with_extension("json");
+                let metadata = file.metadata().ok()?;
+                let sidecar = fs::metadata(&timings).map(|m| m.len()).unwrap_or(0);
+                Some(CacheEntry {
+                    bytes: metadata.len() + sidecar,
+                    used: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                    wav,
+                    timings,
+                })
+            })
+            .collect()
+    }
+
+    /// Remove least recently used entries until the cache fits its limit
+    fn evict(&self) -> Result<(), CacheError> {
+        let _guard = self.lock.lock().unwrap();
+        let max_bytes = self.max_bytes.load(Ordering::Relaxed);
+        let mut entries = self.entries();
+        let mut total: u64 = entries.iter().map(|e| e.bytes).sum();
+        if total <= max_bytes {
+            return Ok(());
+        }
+
+        entries.sort_by_key(|e| e.used);
+        for entry in entries {
+            if total <= max_bytes {
+                break;
+            }
+            fs::remove_file(&entry.wav).map_err(|e| io_error("remove", &entry.wav, e))?;
+            let _ = fs::remove_file(&entry.timings);
+            total -= entry.bytes;
+        }
+        Ok(())
+    }
+
+    /// Remember why a chunk couldn't be stored. A cache that can't be
+    /// written only costs a re-synthesis later, so it isn't an error.
+    fn record(&self, stored: Result<(), CacheError>) {
+        *self.last_error.lock().unwrap() = stored.err().map(|e| e.message);
+    }
+
+    pub fn usage(&self) -> CacheUsage {
+        let entries = self.entries();
+        CacheUsage {
+            path: self.dir.to_string_lossy().to_string(),
+            entries: entries.len(),
+            bytes: entries.iter().map(|e| e.bytes).sum(),
+            max_bytes: self.max_bytes.load(Ordering::Relaxed),
+            last_error: self.last_error.lock().unwrap().clone(),
+        }
+    }
+
+    /// Delete every cached chunk
+    pub fn clear(&self) -> Result<(), CacheError> {
+        let _guard = self.lock.lock().unwrap();
+        if !self.dir.exists() {
+            return Ok(());
+        }
+        fs::remove_dir_all(&self.dir).map_err(|e| io_error("remove", &self.dir, e))
+    }
+}
+
+/// Serves repeated synthesis requests from a `SynthesisCache`
+pub struct CachedBackend {
+    inner: Arc<dyn TtsBackend>,
+    cache: Arc<SynthesisCache>,
+}
+
+impl CachedBackend {
+    pub fn new(inner: Arc<dyn TtsBackend>, cache: Arc<SynthesisCache>) -> Self {
+        CachedBackend { inner, cache }
+    }
+}
+
+impl TtsBackend for CachedBackend {
+    fn kind(&self) -> BackendKind {
+        self.inner.kind()
+    }
+
+    fn is_available(&self) -> bool {
+        self.inner.is_available()
+    }
+
+    fn list_voices(&self) -> Vec<VoiceInfo> {
+        self.inner.list_voices()
+    }
+
+    fn cancel(&self) {
+        self.inner.cancel();
+    }
+
+    fn sample_rate(&self, profile: &VoiceProfile) -> Option<u32> {
+        self.inner.sample_rate(profile)
+    }
+
+    fn synthesize(
+        &self,
+        text: &str,
+        output_path: &str,
+        profile: &VoiceProfile,
+    ) -> Result<TtsResult, TtsError> {
+        let engine = self.inner.engine_identity(profile)?;
+        let key = cache_key(self.inner.kind(), &engine, profile, text);
+        if let Some(result) = self.cache.fetch(&key, Path::new(output_path)) {
+            return Ok(result);
+        }
+
+        let result = self.inner.synthesize(text, output_path, profile)?;
+        self.cache.record(self.cache.store(&key, &result));
+        Ok(result)
+    }
+
+    fn synthesize_raw(&self, text: &str, profile: &VoiceProfile) -> Result<RawAudio, TtsError> {
+        let engine = self.inner.engine_identity(profile)?;
+        let key = cache_key(self.inner.kind(), &engine, profile, text);
+        if let Some(audio) = self.cache.fetch_raw(&key) {
+            return Ok(audio);
+        }
+
+        let audio = self.inner.synthesize_raw(text, profile)?;
+        self.cache.record(self.cache.store_raw(&key, &audio));
+        Ok(audio)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_engine::MockBackend;
+    use std::sync::atomic::AtomicUsize;
+
+    /// Counts the requests that reach the real engine
+    struct CountingBackend {
+        calls: AtomicUsize,
+    }
+
+    impl TtsBackend for CountingBackend {
+        fn kind(&self) -> BackendKind {
+            BackendKind::Mock
+        }
+
+        fn is_available(&self) -> bool {
+            true
+        }
+
+        fn list_voices(&self) -> Vec<VoiceInfo> {
+            Vec::new()
+        }
+
+        fn synthesize(
+            &self,
+            text: &str,
+            output_path: &str,
+            profile: &VoiceProfile,
+        ) -> Result<TtsResult, TtsError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            MockBackend::new().synthesize(text, output_path, profile)
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_cache_key_normalizes_whitespace() {
+        let profile = VoiceProfile::default();
+        let key = cache_key(BackendKind::Piper, "piper|amy.onnx", &profile, "Hello  there.\n");
+        assert_eq!(key, cache_key(BackendKind::Piper, "piper|amy.onnx", &profile, " Hello there."));
+        assert_ne!(key, cache_key(BackendKind::Espeak, "piper|amy.onnx", &profile, "Hello there."));
+        // The default voice resolving to another model is another voice
+        assert_ne!(key, cache_key(BackendKind::Piper, "piper|lessac.onnx", &profile, "Hello there."));
+
+        let slower = VoiceProfile {
+            length_scale: Some(1.2),
+            ..Default::default()
+        };
+        assert_ne!(key, cache_key(BackendKind::Piper, "piper|amy.onnx", &slower, "Hello there."));
+    }
+
+    #[test]
+    fn test_repeated_text_is_served_from_cache() {
+        let dir = temp_dir("synthesis_cache");
+        let inner = Arc::new(CountingBackend {
+            calls: AtomicUsize::new(0),
+        });
+        let cache = Arc::new(SynthesisCache::new(dir.join("cache"), u64::MAX));
+        let backend = CachedBackend::new(inner.clone(), cache.clone());
+        let profile = VoiceProfile::default();
+        let output = dir.join("out.wav").to_string_lossy().to_string();
+
+        let first = backend.synthesize("Say it once.", &output, &profile).unwrap();
+        let second = backend.synthesize("Say  it once.", &output, &profile).unwrap();
+        assert_eq!(inner.calls.load(Ordering::SeqCst), 1);
+        assert_eq!(first.duration_ms, second.duration_ms);
+        assert_eq!(first.word_timings.len(), second.word_timings.len());
+
+        // Shrinking the limit evicts the least recently used entry
+        backend.synthesize("Something else.", &output, &profile).unwrap();
+        assert_eq!(cache.usage().entries, 2);
+        let one_entry = cache.usage().bytes / 2;
+        cache.set_max_bytes(one_entry + one_entry / 2);
+        assert_eq!(cache.usage().entries, 1);
+
+        cache.clear().unwrap();
+        assert_eq!(cache.usage().entries, 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
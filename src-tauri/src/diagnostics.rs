@@ -0,0 +1,85 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::Instant;
+
+use crate::tts_engine::{
+    BackendKind, TtsBackend, TtsConfigReport, TtsError, TtsErrorKind, VoiceProfile,
+};
+
+/// Sentence synthesized by the self-test
+pub const SELF_TEST_TEXT: &str = "This is a test of the speech engine.";
+
+/// Outcome of synthesizing a short test sentence
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SelfTestReport {
+    pub backend: BackendKind,
+    pub ok: bool,
+    /// Wall-clock time the synthesis took
+    pub elapsed_ms: u64,
+    pub audio_ms: u64,
+    pub error: Option<TtsError>,
+}
+
+/// Configuration checks and a self-test, for troubleshooting a broken setup
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TtsDiagnostics {
+    pub config: TtsConfigReport,
+    pub self_test: SelfTestReport,
+    /// Set when the settings file was invalid and defaults are in use
+    pub settings_warning: Option<String>,
+}
+
+/// Synthesize `SELF_TEST_TEXT` into `output_path` and check that audio and
+/// timings came back. The file is removed afterwards.
+pub fn self_test(backend: &dyn TtsBackend, profile: &VoiceProfile, output_path: &Path) -> SelfTestReport {
+    let started = Instant::now();
+    let result = backend
+        .synthesize(SELF_TEST_TEXT, &output_path.to_string_lossy(), profile)
+        .and_then(|result| {
+            let words = SELF_TEST_TEXT.split_whitespace().count();
+            if result.duration_ms == 0 {
+                Err(TtsError::new(TtsErrorKind::Other, "The engine produced no audio"))
+            } else if result.word_timings.len() != words {
+                Err(TtsError::new(
+                    TtsErrorKind::Other,
+                    format!("Expected {} word timings, got {}", words, result.word_timings.len()),
+                ))
+            } else {
+                Ok(result)
+            }
+        });
+    let elapsed_ms = started.elapsed().as_millis() as u64;
+    let _ = std::fs::remove_file(output_path);
+
+    match result {
+        Ok(result) => SelfTestReport {
+            backend: backend.kind(),
+            ok: true,
+            elapsed_ms,
+            audio_ms: result.duration_ms,
+            error: None,
+        },
+        Err(error) => SelfTestReport {
+            backend: backend.kind(),
+            ok: false,
+            elapsed_ms,
+            audio_ms: 0,
+            error: Some(error),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_engine::MockBackend;
+
+    #[test]
+    fn test_self_test_with_mock_engine() {
+        let path = std::env::temp_dir().join(format!("self_test_{}.wav", std::process::id()));
+        let report = self_test(&MockBackend::new(), &VoiceProfile::default(), &path);
+        assert!(report.ok, "{:?}", report.error);
+        assert!(report.audio_ms > 0);
+        assert!(!path.exists());
+    }
+}
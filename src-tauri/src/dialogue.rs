@@ -0,0 +1,357 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::ssml::{render_segments, render_segments_raw, SpokenUnit, VoiceSegment};
+use crate::tts_engine::{
+    BackendKind, RawAudio, TtsBackend, TtsError, TtsErrorKind, TtsResult, VoiceInfo,
+    VoiceProfile,
+};
+use crate::wav::{resample, resample_wav};
+
+/// Voices for reading fiction: quoted speech in one, the rest in another.
+/// Unset profile fields fall back to the document's voice profile.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DialogueVoices {
+    pub enabled: bool,
+    pub narrator: VoiceProfile,
+    pub dialogue: VoiceProfile,
+}
+
+/// Who is speaking a stretch of text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Narrator,
+    Dialogue,
+}
+
+/// Split text into runs of narration and quoted speech, word by word. A
+/// word belongs to the dialogue if it opens a quote or falls inside one, so
+/// quote marks stay attached to the words they were written with.
+pub fn split_dialogue(text: &str) -> Vec<(Role, Vec<String>)> {
+    let mut runs: Vec<(Role, Vec<String>)> = Vec::new();
+    let mut in_quote = false;
+
+    for word in text.split_whitespace() {
+        let opens = word
+            .trim_start_matches(['(', '['])
+            .starts_with(['"', '\u{201c}', '\u{ab}']);
+        let role = if in_quote || opens {
+            Role::Dialogue
+        } else {
+            Role::Narrator
+        };
+
+        in_quote = quote_open_after(word, in_quote);
+
+        match runs.last_mut() {
+            Some((last, words)) if *last == role => words.push(word.to_string()),
+            _ => runs.push((role, vec![word.to_string()])),
+        }
+    }
+
+    runs
+}
+
+/// Whether a quotation is open after `text`, given whether one was open
+/// before it
+pub fn quote_open_after(text: &str, open: bool) -> bool {
+    text.chars().fold(open, |open, c| match c {
+        '\u{201c}' | '\u{ab}' => true,
+        '\u{201d}' | '\u{bb}' => false,
+        '"' => !open,
+        _ => open,
+    })
+}
+
+/// Reads quoted speech and narration with different voices, stitching the
+/// runs into one recording per request
+pub struct DialogueBackend {
+    inner: Arc<dyn TtsBackend>,
+    voices: DialogueVoices,
+}
+
+impl DialogueBackend {
+    pub fn new(inner: Arc<dyn TtsBackend>, voices: DialogueVoices) -> Self {
+        DialogueBackend { inner, voices }
+    }
+
+    fn profile(&self, role: Role, base: &VoiceProfile) -> VoiceProfile {
+        match role {
+            Role::Narrator => self.voices.narrator.or(base),
+            Role::Dialogue => self.voices.dialogue.or(base),
+        }
+    }
+
+    fn voice_segments(&self, runs: Vec<(Role, Vec<String>)>, base: &VoiceProfile) -> Vec<VoiceSegment> {
+        runs.into_iter()
+            .map(|(role, words)| VoiceSegment::Speech {
+                units: words.iter().map(|word| SpokenUnit::word(word)).collect(),
+                profile: self.profile(role, base),
+            })
+            .collect()
+    }
+}
+
+impl TtsBackend for DialogueBackend {
+    fn kind(&self) -> BackendKind {
+        self.inner.kind()
+    }
+
+    fn is_available(&self) -> bool {
+        self.inner.is_available()
+    }
+
+    fn list_voices(&self) -> Vec<VoiceInfo> {
+        self.inner.list_voices()
+    }
+
+    fn cancel(&self) {
+        self.inner.cancel();
+    }
+
+    fn sample_rate(&self, profile: &VoiceProfile) -> Option<u32> {
+        self.inner.sample_rate(&self.profile(Role::Narrator, profile))
+    }
+
+    fn synthesize(
+        &self,
+        text: &str,
+        output_path: &str,
+        profile: &VoiceProfile,
+    ) -> Result<TtsResult, TtsError> {
+        // Every chunk is written at the narrator's rate, so a document's
+        // chunks can be joined whichever voices speak them
+        let sample_rate = self.sample_rate(profile);
+        let runs = split_dialogue(text);
+        if let [(role, _)] = runs.as_slice() {
+            let result = self.inner.synthesize(text, output_path, &self.profile(*role, profile))?;
+            if let Some(rate) = sample_rate {
+                resample_wav(Path::new(output_path), rate)
+                    .map_err(|e| TtsError::new(TtsErrorKind::Io, e.message))?;
+            }
+            return Ok(result);
+        }
+
+        let segments = self.voice_segments(runs, profile);
+        render_segments(self.inner.as_ref(), segments, output_path, sample_rate)
+    }
+
+    fn synthesize_raw(&self, text: &str, profile: &VoiceProfile) -> Result<RawAudio, TtsError> {
+        let sample_rate = self.sample_rate(profile);
+        let runs = split_dialogue(text);
+        if let [(role, _)] = runs.as_slice() {
+            let mut audio = self.inner.synthesize_raw(text, &self.profile(*role, profile))?;
+            if let Some(rate) = sample_rate.filter(|_| audio.channels == 1) {
+                audio.samples = resample(&audio.samples, audio.sample_rate, rate);
+                audio.sample_rate = rate;
+            }
+            return Ok(audio);
+        }
+
+        let segments = self.voice_segments(runs, profile);
+        render_segments_raw(self.inner.as_ref(), segments, sample_rate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jobs::CancelToken;
+    use crate::mock_engine::{MockBackend, MOCK_SAMPLE_RATE};
+    use crate::synthesis::{
+        split_into_chunks, synthesize_document, ChunkStatus, SynthesisOptions, MAX_CHUNK_CHARS,
+    };
+    use crate::wav::read_wav_samples;
+    use std::sync::Mutex;
+
+    /// Records the voice each request was made with
+    struct RecordingBackend {
+        requests: Mutex<Vec<(String, Option<String>)>>,
+    }
+
+    impl TtsBackend for RecordingBackend {
+        fn kind(&self) -> BackendKind {
+            BackendKind::Mock
+        }
+
+        fn is_available(&self) -> bool {
+            true
+        }
+
+        fn list_voices(&self) -> Vec<VoiceInfo> {
+            Vec::new()
+        }
+
+        fn synthesize(
+            &self,
+            text: &str,
+            output_path: &str,
+            profile: &VoiceProfile,
+        ) -> Result<TtsResult, TtsError> {
+            self.requests
+                .lock()
+                .unwrap()
+                .push((text.to_string(), profile.voice_id.clone()));
+            MockBackend::new().synthesize(text, output_path, profile)
+        }
+    }
+
+    /// Mock voices recorded at different rates: "medium" at 22.05 kHz,
+    /// any other at the mock's own 16 kHz
+    struct RatedBackend;
+
+    fn voice_rate(profile: &VoiceProfile) -> u32 {
+        match profile.voice_id.as_deref() {
+            Some("medium") => 22050,
+            _ => MOCK_SAMPLE_RATE,
+        }
+    }
+
+    impl TtsBackend for RatedBackend {
+        fn kind(&self) -> BackendKind {
+            BackendKind::Mock
+        }
+
+        fn is_available(&self) -> bool {
+            true
+        }
+
+        fn list_voices(&self) -> Vec<VoiceInfo> {
+            Vec::new()
+        }
+
+        fn sample_rate(&self, profile: &VoiceProfile) -> Option<u32> {
+            Some(voice_rate(profile))
+        }
+
+        fn synthesize(
+            &self,
+            text: &str,
+            output_path: &str,
+            profile: &VoiceProfile,
+        ) -> Result<TtsResult, TtsError> {
+            let result = MockBackend::new().synthesize(text, output_path, profile)?;
+            resample_wav(Path::new(output_path), voice_rate(profile))
+                .map_err(|e| TtsError::new(TtsErrorKind::Io, e.message))?;
+            Ok(result)
+        }
+    }
+
+    fn roles(text: &str) -> Vec<(Role, String)> {
+        split_dialogue(text)
+            .into_iter()
+            .map(|(role, words)| (role, words.join(" ")))
+            .collect()
+    }
+
+    #[test]
+    fn test_split_dialogue() {
+        assert_eq!(
+            roles("\"Wait,\" she said. \"Not yet.\""),
+            vec![
+                (Role::Dialogue, "\"Wait,\"".to_string()),
+                (Role::Narrator, "she said.".to_string()),
+                (Role::Dialogue, "\"Not yet.\"".to_string()),
+            ]
+        );
+        assert_eq!(
+            roles("He shouted \u{201c}Run now!\u{201d} and ran."),
+            vec![
+                (Role::Narrator, "He shouted".to_string()),
+                (Role::Dialogue, "\u{201c}Run now!\u{201d}".to_string()),
+                (Role::Narrator, "and ran.".to_string()),
+            ]
+        );
+        assert_eq!(roles("No quotes here."), vec![(Role::Narrator, "No quotes here.".to_string())]);
+    }
+
+    #[test]
+    fn test_dialogue_uses_its_own_voice() {
+        let inner = Arc::new(RecordingBackend {
+            requests: Mutex::new(Vec::new()),
+        });
+        let voices = DialogueVoices {
+            enabled: true,
+            narrator: VoiceProfile {
+                voice_id: Some("narrator".to_string()),
+                ..Default::default()
+            },
+            dialogue: VoiceProfile {
+                voice_id: Some("dialogue".to_string()),
+                ..Default::default()
+            },
+        };
+        let backend = DialogueBackend::new(inner.clone(), voices);
+        let path = std::env::temp_dir().join(format!("dialogue_{}.wav", std::process::id()));
+        let text = "\"Wait,\" she said. \"Not yet.\"";
+        let result = backend
+            .synthesize(text, &path.to_string_lossy(), &VoiceProfile::default())
+            .unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        let voices: Vec<Option<String>> = inner
+            .requests
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(_, voice)| voice.clone())
+            .collect();
+        assert_eq!(
+            voices,
+            vec![
+                Some("dialogue".to_string()),
+                Some("narrator".to_string()),
+                Some("dialogue".to_string()),
+            ]
+        );
+
+        // One timing per word, in order and within the stitched audio
+        let words: Vec<&str> = result.word_timings.iter().map(|t| t.word.as_str()).collect();
+        assert_eq!(words, text.split_whitespace().collect::<Vec<_>>());
+        assert!(result
+            .word_timings
+            .windows(2)
+            .all(|pair| pair[0].end_ms <= pair[1].start_ms));
+        assert!(result.word_timings.last().unwrap().end_ms <= result.duration_ms);
+    }
+
+    #[test]
+    fn test_voices_at_different_rates_share_the_narrator_rate() {
+        let voices = DialogueVoices {
+            enabled: true,
+            narrator: VoiceProfile {
+                voice_id: Some("low".to_string()),
+                ..Default::default()
+            },
+            dialogue: VoiceProfile {
+                voice_id: Some("medium".to_string()),
+                ..Default::default()
+            },
+        };
+        let backend = DialogueBackend::new(Arc::new(RatedBackend), voices);
+        let paragraphs = vec![
+            "\"Only dialogue here.\"".to_string(),
+            "\"Hello there,\" she said.".to_string(),
+            "Only narration.".to_string(),
+        ];
+        let dir = std::env::temp_dir().join(format!("dialogue_rates_{}", std::process::id()));
+        let manifest = synthesize_document(
+            &backend,
+            split_into_chunks(&paragraphs, MAX_CHUNK_CHARS),
+            &dir,
+            &VoiceProfile::default(),
+            &SynthesisOptions::default(),
+            &CancelToken::new(),
+            &|_| {},
+        )
+        .unwrap();
+        let (spec, _) = read_wav_samples(Path::new(&manifest.audio_path)).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(manifest.chunks.iter().all(|c| c.status == ChunkStatus::Done));
+        assert_eq!(spec.sample_rate, MOCK_SAMPLE_RATE);
+    }
+}
@@ -0,0 +1,177 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::alignment::align_wav;
+use crate::jobs::CancelToken;
+use crate::tts_engine::{
+    run_with_stdin, synthesis_timeout, BackendKind, TtsBackend, TtsError, TtsErrorKind,
+    TtsResult, VoiceInfo, VoiceProfile,
+};
+
+/// Executable names, newest first
+const ESPEAK_EXES: [&str; 2] = ["espeak-ng", "espeak"];
+const DEFAULT_VOICE: &str = "en-us";
+/// espeak's default speaking rate in words per minute
+const DEFAULT_WPM: f32 = 175.0;
+/// espeak always writes 22.05 kHz audio
+const SAMPLE_RATE: u32 = 22050;
+
+/// eSpeak NG formant synthesizer, for machines without Piper
+pub struct EspeakBackend {
+    program: Option<PathBuf>,
+    cancel: CancelToken,
+}
+
+impl EspeakBackend {
+    pub fn new() -> Self {
+        let program = ESPEAK_EXES.iter().find_map(|exe| which::which(exe).ok());
+        EspeakBackend {
+            program,
+            cancel: CancelToken::new(),
+        }
+    }
+
+    fn program(&self) -> Result<&PathBuf, TtsError> {
+        self.program.as_ref().ok_or_else(|| {
+            TtsError::new(
+                TtsErrorKind::EngineNotFound,
+                format!("eSpeak NG not found on PATH (looked for {})", ESPEAK_EXES.join(", ")),
+            )
+        })
+    }
+}
+
+impl TtsBackend for EspeakBackend {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Espeak
+    }
+
+    fn is_available(&self) -> bool {
+        self.program.is_some()
+    }
+
+    fn list_voices(&self) -> Vec<VoiceInfo> {
+        let Ok(program) = self.program() else {
+            return Vec::new();
+        };
+
+        match Command::new(program).arg("--voices").output() {
+            Ok(output) if output.status.success() => {
+                parse_voice_list(&String::from_utf8_lossy(&output.stdout))
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn sample_rate(&self, _profile: &VoiceProfile) -> Option<u32> {
+        Some(SAMPLE_RATE)
+    }
+
+    fn synthesize(
+        &self,
+        text: &str,
+        output_path: &str,
+        profile: &VoiceProfile,
+    ) -> Result<TtsResult, TtsError> {
+        if profile.speaker_id.is_some_and(|id| id != 0) {
+            return Err(TtsError::new(
+                TtsErrorKind::InvalidProfile,
+                "eSpeak NG voices have a single speaker",
+            ));
+        }
+
+        let voice = profile
+            .voice_id
+            .as_deref()
+            .map(espeak_voice)
+            .unwrap_or_else(|| DEFAULT_VOICE.to_string());
+        // length_scale stretches phonemes, so it divides the speaking rate
+        let wpm = (DEFAULT_WPM / profile.length_scale.unwrap_or(1.0)).clamp(80.0, 450.0);
+
+        let mut command = Command::new(self.program()?);
+        command.args([
+            "-v",
+            &voice,
+            "-s",
+            &(wpm.round() as u32).to_string(),
+            "-w",
+            output_path,
+            "--stdin",
+        ]);
+        run_with_stdin(command, text, "eSpeak NG", synthesis_timeout(text), &self.cancel)?;
+
+        let (word_timings, duration_ms) = align_wav(text, Path::new(output_path))?;
+
+        Ok(TtsResult {
+            audio_path: output_path.to_string(),
+            word_timings,
+            duration_ms,
+        })
+    }
+
+    fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    fn engine_identity(&self, _profile: &VoiceProfile) -> Result<String, TtsError> {
+        Ok(self.program()?.display().to_string())
+    }
+}
+
+/// Map a Piper voice id such as `en_US-amy-medium` to its espeak language,
+/// so a default profile chosen for Piper still works here
+fn espeak_voice(id: &str) -> String {
+    if id.contains('_') {
+        id.split('-').next().unwrap_or(id).replace('_', "-").to_lowercase()
+    } else {
+        id.to_string()
+    }
+}
+
+/// Parse the table printed by `espeak-ng --voices`:
+/// `Pty Language Age/Gender VoiceName File Other Languages`
+fn parse_voice_list(output: &str) -> Vec<VoiceInfo> {
+    output
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let columns: Vec<&str> = line.split_whitespace().collect();
+            if columns.len() < 5 {
+                return None;
+            }
+            Some(VoiceInfo {
+                id: columns[1].to_string(),
+                name: columns[3].replace('_', " "),
+                language: columns[1].to_string(),
+                quality: None,
+                sample_rate: Some(SAMPLE_RATE),
+                speakers: Vec::new(),
+                model_path: columns[4].to_string(),
+                available: true,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_voice_list() {
+        let output = "Pty Language       Age/Gender VoiceName          File                 Other Languages\n \
+                      5  af              --/M      Afrikaans          gmw/af\n \
+                      2  en-us           --/M      English_(America)  gmw/en-US            (en 3)\n";
+        let voices = parse_voice_list(output);
+        assert_eq!(voices.len(), 2);
+        assert_eq!(voices[1].id, "en-us");
+        assert_eq!(voices[1].name, "English (America)");
+        assert_eq!(voices[1].model_path, "gmw/en-US");
+    }
+
+    #[test]
+    fn test_espeak_voice_from_piper_id() {
+        assert_eq!(espeak_voice("en_US-amy-medium"), "en-us");
+        assert_eq!(espeak_voice("en-gb"), "en-gb");
+    }
+}
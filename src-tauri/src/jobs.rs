@@ -0,0 +1,176 @@
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::process::Child;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::synthesis::{
+    synthesize_document, SynthesisManifest, SynthesisOptions, SynthesisProgress, TextChunk,
+};
+use crate::tts_engine::{TtsBackend, TtsError, TtsErrorKind, VoiceProfile};
+
+pub type JobId = u64;
+
+/// Shared cancellation flag. Engine processes registered while running are
+/// killed when it is cancelled, so long chunks stop immediately.
+#[derive(Clone, Default)]
+pub struct CancelToken {
+    inner: Arc<CancelState>,
+}
+
+#[derive(Default)]
+struct CancelState {
+    cancelled: AtomicBool,
+    children: Mutex<Vec<Arc<Mutex<Child>>>>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+        for child in self.inner.children.lock().unwrap().drain(..) {
+            let _ = child.lock().unwrap().kill();
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Run `f` while `child` is working for this token, killing it if the
+    /// token is cancelled in the meantime
+    pub fn while_running<T>(&self, child: &Arc<Mutex<Child>>, f: impl FnOnce() -> T) -> T {
+        self.inner.children.lock().unwrap().push(child.clone());
+        // `cancel` sets the flag before draining, so a cancel that missed
+        // the registration above is seen here
+        if self.is_cancelled() {
+            let _ = child.lock().unwrap().kill();
+        }
+        let result = f();
+        self.inner
+            .children
+            .lock()
+            .unwrap()
+            .retain(|c| !Arc::ptr_eq(c, child));
+        result
+    }
+
+    /// The error returned by work stopped through this token
+    pub fn error(&self) -> TtsError {
+        TtsError::new(TtsErrorKind::Cancelled, "Synthesis cancelled")
+    }
+}
+
+/// Progress and outcome of a background synthesis job
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JobEvent {
+    Progress {
+        job_id: JobId,
+        #[serde(flatten)]
+        progress: SynthesisProgress,
+    },
+    Finished {
+        job_id: JobId,
+        manifest: SynthesisManifest,
+    },
+    Failed {
+        job_id: JobId,
+        error: String,
+        /// Set when the engine failed, as opposed to loading the audio
+        kind: Option<TtsErrorKind>,
+    },
+    /// Partial files have been removed
+    Cancelled { job_id: JobId },
+}
+
+/// A document being synthesized on a background thread
+pub struct SynthesisJob {
+    pub id: JobId,
+    cancel: CancelToken,
+    backend: Arc<dyn TtsBackend>,
+    thread: JoinHandle<()>,
+}
+
+impl SynthesisJob {
+    /// Stop synthesis, killing engine processes mid-chunk
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+        self.backend.cancel();
+    }
+
+    /// Wait for the job's thread to finish
+    pub fn join(self) {
+        let _ = self.thread.join();
+    }
+}
+
+/// Synthesize a document on a background thread, reporting through
+/// `on_event`. Each job writes into its own directory under `out_root`, so
+/// audio still being played isn't touched. `on_finished` runs before the
+/// finished event is sent, so the audio can be loaded before listeners hear
+/// about it.
+pub fn start_job<D, F>(
+    backend: Arc<dyn TtsBackend>,
+    chunks: Vec<TextChunk>,
+    out_root: PathBuf,
+    profile: VoiceProfile,
+    options: SynthesisOptions,
+    on_finished: D,
+    on_event: F,
+) -> SynthesisJob
+where
+    D: FnOnce(&SynthesisManifest) -> Result<(), String> + Send + 'static,
+    F: Fn(JobEvent) + Send + Sync + 'static,
+{
+    static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let out_dir = out_root.join(format!("job_{}", id));
+    let cancel = CancelToken::new();
+    let token = cancel.clone();
+    let job_backend = backend.clone();
+
+    let thread = thread::spawn(move || {
+        let result = synthesize_document(
+            job_backend.as_ref(),
+            chunks,
+            &out_dir,
+            &profile,
+            &options,
+            &token,
+            &|progress| on_event(JobEvent::Progress { job_id: id, progress }),
+        );
+
+        let event = match result {
+            Ok(manifest) => match on_finished(&manifest) {
+                Ok(()) => JobEvent::Finished {
+                    job_id: id,
+                    manifest,
+                },
+                Err(error) => JobEvent::Failed {
+                    job_id: id,
+                    error,
+                    kind: None,
+                },
+            },
+            Err(_) if token.is_cancelled() => JobEvent::Cancelled { job_id: id },
+            Err(e) => JobEvent::Failed {
+                job_id: id,
+                error: e.message,
+                kind: Some(e.kind),
+            },
+        };
+        on_event(event);
+    });
+
+    SynthesisJob {
+        id,
+        cancel,
+        backend,
+        thread,
+    }
+}
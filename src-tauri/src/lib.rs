@@ -1,20 +1,90 @@
 // Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
-use std::sync::Mutex;
-use tauri::{Manager, State};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tauri::{Emitter, Manager, State};
 
+mod alignment;
 mod audio;
+mod cache;
+mod diagnostics;
+mod dialogue;
+mod espeak_engine;
+mod jobs;
+mod loudness;
+mod navigation;
+mod mock_engine;
+mod pauses;
 mod pdf_parser;
+mod piper_worker;
+mod settings;
+mod ssml;
+mod streaming;
+mod stretch;
+mod synthesis;
 mod tts_engine;
+mod wav;
 
-use audio::{create_audio_controller, AudioController, AudioState};
+use audio::{create_audio_controller, list_output_devices, AudioController, AudioState, OutputDevice};
+use cache::{CacheUsage, SynthesisCache};
+use diagnostics::{self_test, TtsDiagnostics};
+use jobs::{start_job, JobId, SynthesisJob};
+use navigation::{SkipUnit, TextMap};
+use pauses::plan_chunks;
 use pdf_parser::{extract_pdf_text, TextContent};
-use tts_engine::{estimate_word_timings, generate_audio, is_piper_available, get_available_voices, TtsResult, VoiceInfo, WordTiming};
+use piper_worker::{WorkerPool, WorkerStatus};
+use settings::{load_settings, save_settings, settings_file, AppSettings};
+use ssml::{plan_ssml, SsmlJob};
+use streaming::{start_stream, StreamEvent, StreamHandle, DEFAULT_LOOKAHEAD_MS, STREAM_CHUNK_CHARS};
+use synthesis::{default_concurrency, remove_other_outputs, SynthesisOptions, TextChunk, MAX_CHUNK_CHARS};
+use tts_engine::{create_backend, estimate_word_timings, wrap_backend, list_backends, validate_config, BackendInfo, BackendKind, TtsBackend, TtsConfig, TtsConfigReport, VoiceInfo, VoiceProfile, WordTiming};
 
 // App state for managing audio player
 pub struct AppState {
     audio_controller: AudioController,
     current_text: Mutex<String>,
     temp_audio_path: Mutex<Option<String>>,
+    settings: Mutex<AppSettings>,
+    settings_path: Mutex<Option<PathBuf>>,
+    /// Why the settings file couldn't be loaded, if it couldn't
+    settings_warning: Mutex<Option<String>>,
+    stream: Mutex<Option<StreamHandle>>,
+    cache: Mutex<Option<Arc<SynthesisCache>>>,
+    job: Mutex<Option<SynthesisJob>>,
+}
+
+impl AppState {
+    fn tts_config(&self) -> TtsConfig {
+        TtsConfig::from_settings(&self.settings.lock().unwrap())
+    }
+
+    /// Stop feeding a streaming session, if one is running
+    fn cancel_stream(&self) {
+        if let Some(stream) = self.stream.lock().unwrap().take() {
+            stream.cancel();
+        }
+    }
+
+    /// The requested voice profile over the persisted default, with the
+    /// pause policy's sentence pause unless the voice sets its own
+    fn voice_profile(&self, voice: Option<VoiceProfile>) -> VoiceProfile {
+        let settings = self.settings.lock().unwrap();
+        let mut profile = voice.unwrap_or_default().or(&settings.voice);
+        profile
+            .sentence_silence
+            .get_or_insert(settings.pauses.sentence_ms as f32 / 1000.0);
+        profile
+    }
+
+    /// The requested engine, or the one chosen in settings, behind the
+    /// synthesis cache
+    fn tts_backend(&self, kind: Option<BackendKind>) -> Arc<dyn TtsBackend> {
+        let (kind, dialogue) = {
+            let settings = self.settings.lock().unwrap();
+            (kind.unwrap_or_else(|| settings.effective_backend()), settings.dialogue.clone())
+        };
+        let cache = self.cache.lock().unwrap().clone();
+        wrap_backend(create_backend(kind, &self.tts_config()), cache, dialogue)
+    }
 }
 
 impl Default for AppState {
@@ -23,6 +93,12 @@ impl Default for AppState {
             audio_controller: create_audio_controller(),
             current_text: Mutex::new(String::new()),
             temp_audio_path: Mutex::new(None),
+            settings: Mutex::new(AppSettings::default()),
+            settings_path: Mutex::new(None),
+            settings_warning: Mutex::new(None),
+            stream: Mutex::new(None),
+            cache: Mutex::new(None),
+            job: Mutex::new(None),
         }
     }
 }
@@ -38,56 +114,334 @@ fn extract_pdf(path: String) -> Result<TextContent, String> {
     extract_pdf_text(&path).map_err(|e| e.message)
 }
 
-/// Check if Piper TTS is available
+/// Check if a TTS engine is available
+#[tauri::command]
+fn check_tts_available(backend: Option<BackendKind>, state: State<AppState>) -> bool {
+    state.tts_backend(backend).is_available()
+}
+
+/// List the TTS engines and whether each is installed
 #[tauri::command]
-fn check_tts_available() -> bool {
-    is_piper_available()
+fn get_tts_backends(state: State<AppState>) -> Vec<BackendInfo> {
+    list_backends(&state.tts_config())
 }
 
 /// Get available voices
 #[tauri::command]
-fn get_voices() -> Vec<VoiceInfo> {
-    get_available_voices()
+fn get_voices(backend: Option<BackendKind>, state: State<AppState>) -> Vec<VoiceInfo> {
+    state.tts_backend(backend).list_voices()
+}
+
+/// Get the current settings
+#[tauri::command]
+fn get_settings(state: State<AppState>) -> AppSettings {
+    state.settings.lock().unwrap().clone()
+}
+
+/// Replace the settings and persist them
+#[tauri::command]
+fn update_settings(settings: AppSettings, state: State<AppState>) -> Result<(), String> {
+    // Switching devices reopens the output, so only do it for a new device,
+    // and before saving since it can fail. The audio thread is waited for
+    // without the settings lock, so other commands aren't held up.
+    let previous_device = state.settings.lock().unwrap().output_device.clone();
+    let device_changed = settings.output_device != previous_device;
+    if device_changed {
+        state
+            .audio_controller
+            .set_output_device(settings.output_device.clone())
+            .map_err(|e| e.message)?;
+    }
+    let mut current = state.settings.lock().unwrap();
+    if let Some(path) = state.settings_path.lock().unwrap().as_ref() {
+        if let Err(e) = save_settings(path, &settings) {
+            drop(current);
+            if device_changed {
+                let _ = state.audio_controller.set_output_device(previous_device);
+            }
+            return Err(e.message);
+        }
+    }
+    if let Some(cache) = state.cache.lock().unwrap().as_ref() {
+        cache.set_max_bytes(settings.cache_max_bytes());
+    }
+    state.audio_controller.set_tick_interval(settings.position_tick_ms());
+    state.audio_controller.set_loudness_settings(settings.loudness.clone());
+    *current = settings;
+    drop(current);
+    // Workers may be running a Piper binary that is no longer configured
+    WorkerPool::global().shutdown();
+    Ok(())
+}
+
+/// Persist the default voice profile used when `prepare_audio` gets no overrides
+#[tauri::command]
+fn set_default_voice(profile: VoiceProfile, state: State<AppState>) -> Result<(), String> {
+    // One lock across the change and the save, so concurrent updates
+    // aren't lost
+    let mut settings = state.settings.lock().unwrap();
+    let mut updated = settings.clone();
+    updated.voice = profile;
+    if let Some(path) = state.settings_path.lock().unwrap().as_ref() {
+        save_settings(path, &updated).map_err(|e| e.message)?;
+    }
+    *settings = updated;
+    Ok(())
+}
+
+/// List the connected audio outputs
+#[tauri::command]
+fn get_output_devices() -> Vec<OutputDevice> {
+    list_output_devices()
+}
+
+/// Play through the named output, or the system default for `None`, and
+/// remember the choice
+#[tauri::command]
+fn set_output_device(name: Option<String>, state: State<AppState>) -> Result<(), String> {
+    if let Some(name) = &name {
+        if !list_output_devices().iter().any(|device| &device.name == name) {
+            return Err(format!("No output device named {}", name));
+        }
+    }
+    // The audio thread is waited for without the settings lock
+    let previous_device = state.settings.lock().unwrap().output_device.clone();
+    state
+        .audio_controller
+        .set_output_device(name.clone())
+        .map_err(|e| e.message)?;
+    let mut settings = state.settings.lock().unwrap();
+    let mut updated = settings.clone();
+    updated.output_device = name;
+    if let Some(path) = state.settings_path.lock().unwrap().as_ref() {
+        if let Err(e) = save_settings(path, &updated) {
+            drop(settings);
+            let _ = state.audio_controller.set_output_device(previous_device);
+            return Err(e.message);
+        }
+    }
+    *settings = updated;
+    Ok(())
+}
+
+/// Check the persistent Piper workers, dropping any that have exited
+#[tauri::command]
+fn get_piper_workers() -> Vec<WorkerStatus> {
+    WorkerPool::global().health_check()
+}
+
+/// Report how much disk the synthesis cache uses
+#[tauri::command]
+fn get_cache_usage(state: State<AppState>) -> Option<CacheUsage> {
+    state.cache.lock().unwrap().as_ref().map(|cache| cache.usage())
+}
+
+/// Delete all cached synthesized audio
+#[tauri::command]
+fn clear_cache(state: State<AppState>) -> Result<Option<CacheUsage>, String> {
+    match state.cache.lock().unwrap().as_ref() {
+        Some(cache) => {
+            cache.clear().map_err(|e| e.message)?;
+            Ok(Some(cache.usage()))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Validate the Piper configuration and report which paths were tried
+#[tauri::command]
+fn validate_tts_config(state: State<AppState>) -> TtsConfigReport {
+    validate_config(&state.tts_config())
 }
 
-/// Generate audio from text and prepare for playback
+/// Check the configuration and synthesize a test sentence, bypassing the
+/// cache so the engine itself is exercised
+#[tauri::command]
+fn run_tts_diagnostics(
+    backend: Option<BackendKind>,
+    voice: Option<VoiceProfile>,
+    state: State<AppState>,
+) -> TtsDiagnostics {
+    let config = state.tts_config();
+    let kind = backend.unwrap_or_else(|| state.settings.lock().unwrap().effective_backend());
+    let output_path = std::env::temp_dir().join(format!("pdf_audiobook_self_test_{}.wav", std::process::id()));
+    TtsDiagnostics {
+        config: validate_config(&config),
+        self_test: self_test(
+            create_backend(kind, &config).as_ref(),
+            &state.voice_profile(voice),
+            &output_path,
+        ),
+        settings_warning: state.settings_warning.lock().unwrap().clone(),
+    }
+}
+
+/// Start generating audio for the document's paragraphs in the background
+/// and return the job id. Progress and the finished manifest are reported
+/// through `synthesis-job` events; the audio is loaded for playback before
+/// the finished event. Paragraphs are synthesized in chunks, so one failing
+/// chunk doesn't lose the whole document. `backend` and `voice` override the
+/// engine and default voice profile for this call only.
 #[tauri::command]
 fn prepare_audio(
-    text: String,
+    paragraphs: Vec<String>,
+    backend: Option<BackendKind>,
+    voice: Option<VoiceProfile>,
     state: State<AppState>,
     app_handle: tauri::AppHandle,
-) -> Result<TtsResult, String> {
+) -> Result<JobId, String> {
+    let chunks = {
+        let settings = state.settings.lock().unwrap();
+        plan_chunks(&paragraphs, MAX_CHUNK_CHARS, &settings.pauses)
+    };
+    start_synthesis(&paragraphs, chunks, backend, voice, &state, app_handle)
+}
+
+/// Like `prepare_audio`, for a hand-prepared SSML script. Each `<p>` is
+/// synthesized as one chunk; the returned paragraphs are the script's text
+/// without markup, which the word timings refer to.
+#[tauri::command]
+fn prepare_ssml(
+    ssml: String,
+    backend: Option<BackendKind>,
+    voice: Option<VoiceProfile>,
+    state: State<AppState>,
+    app_handle: tauri::AppHandle,
+) -> Result<SsmlJob, String> {
+    let script = {
+        let settings = state.settings.lock().unwrap();
+        plan_ssml(&ssml, &settings.pauses).map_err(|e| e.message)?
+    };
+    let job_id = start_synthesis(&script.paragraphs, script.chunks, backend, voice, &state, app_handle)?;
+    Ok(SsmlJob {
+        job_id,
+        paragraphs: script.paragraphs,
+    })
+}
+
+/// Replace any running job with one synthesizing `chunks`
+fn start_synthesis(
+    paragraphs: &[String],
+    chunks: Vec<TextChunk>,
+    backend: Option<BackendKind>,
+    voice: Option<VoiceProfile>,
+    state: &AppState,
+    app_handle: tauri::AppHandle,
+) -> Result<JobId, String> {
     // Create temp directory for audio
     let app_data_dir = app_handle
         .path()
         .app_data_dir()
         .map_err(|e| format!("Failed to get app data dir: {}", e))?;
-    
-    std::fs::create_dir_all(&app_data_dir)
-        .map_err(|e| format!("Failed to create app data dir: {}", e))?;
-    
-    let audio_path = app_data_dir.join("current_audio.wav");
-    let audio_path_str = audio_path.to_string_lossy().to_string();
+    let out_root = app_data_dir.join("synthesis");
 
-    // Generate audio using Piper TTS
-    let result = generate_audio(&text, &audio_path_str).map_err(|e| e.message)?;
+    state.cancel_stream();
+    // Wait for the previous job, so one that was just finishing can't load
+    // its audio over this one's. It is taken out before joining so
+    // `cancel_job` isn't blocked, and cancelling kills its engine, so the
+    // wait is short.
+    let previous = state.job.lock().unwrap().take();
+    if let Some(job) = previous {
+        job.cancel();
+        job.join();
+    }
+
+    // Generate audio with the selected TTS engine
+    let profile = state.voice_profile(voice);
+    let options = {
+        let settings = state.settings.lock().unwrap();
+        SynthesisOptions {
+            concurrency: settings.synthesis_concurrency.unwrap_or_else(default_concurrency),
+            loudness: settings.loudness.clone(),
+        }
+    };
 
     // Store current text for timing
     {
         let mut current_text = state.current_text.lock().unwrap();
-        *current_text = text;
+        *current_text = paragraphs.join(" ");
     }
 
-    // Store audio path
-    {
-        let mut temp_path = state.temp_audio_path.lock().unwrap();
-        *temp_path = Some(audio_path_str.clone());
+    let audio_controller = state.audio_controller.clone();
+    let finished_handle = app_handle.clone();
+    let paragraphs = paragraphs.to_vec();
+    let job = start_job(
+        state.tts_backend(backend),
+        chunks,
+        out_root.clone(),
+        profile,
+        options,
+        move |manifest| {
+            // Load audio into player
+            audio_controller.load(&manifest.audio_path).map_err(|e| e.message)?;
+            // Earlier documents are no longer being played
+            if let Some(out_dir) = Path::new(&manifest.audio_path).parent() {
+                remove_other_outputs(&out_root, out_dir);
+            }
+            audio_controller.set_document_loudness(manifest.loudness.clone(), manifest.leveled);
+            audio_controller.set_text_map(TextMap::new(&paragraphs, manifest.word_timings.clone()));
+            let state = finished_handle.state::<AppState>();
+            *state.temp_audio_path.lock().unwrap() = Some(manifest.audio_path.clone());
+            Ok(())
+        },
+        move |event| {
+            let _ = app_handle.emit("synthesis-job", event);
+        },
+    );
+
+    let id = job.id;
+    *state.job.lock().unwrap() = Some(job);
+    Ok(id)
+}
+
+/// Cancel a synthesis job, stopping the engine and removing partial files
+#[tauri::command]
+fn cancel_job(job_id: JobId, state: State<AppState>) -> bool {
+    match state.job.lock().unwrap().as_ref() {
+        Some(job) if job.id == job_id => {
+            job.cancel();
+            true
+        }
+        _ => false,
     }
+}
+
+/// Start playing the document while it is synthesized. Chunks are reported
+/// through `synthesis-stream` events carrying their word timings, and
+/// synthesis stays a bounded distance ahead of the playhead.
+#[tauri::command]
+fn stream_audio(
+    paragraphs: Vec<String>,
+    backend: Option<BackendKind>,
+    voice: Option<VoiceProfile>,
+    state: State<AppState>,
+    app_handle: tauri::AppHandle,
+) -> Result<(), String> {
+    state.cancel_stream();
+    state.audio_controller.begin_stream().map_err(|e| e.message)?;
 
-    // Load audio into player
-    state.audio_controller.load(&audio_path_str, result.duration_ms).map_err(|e| e.message)?;
+    let chunks = plan_chunks(&paragraphs, STREAM_CHUNK_CHARS, &state.settings.lock().unwrap().pauses);
+    state.audio_controller.set_text_map(TextMap::new(&paragraphs, Vec::new()));
+    let audio_controller = state.audio_controller.clone();
+    let handle = start_stream(
+        state.tts_backend(backend),
+        chunks,
+        state.voice_profile(voice),
+        state.audio_controller.clone(),
+        DEFAULT_LOOKAHEAD_MS,
+        move |event| {
+            if let StreamEvent::Chunk(chunk) = &event {
+                audio_controller.add_word_timings(&chunk.word_timings);
+            }
+            let _ = app_handle.emit("synthesis-stream", event);
+        },
+    );
 
-    Ok(result)
+    *state.current_text.lock().unwrap() = paragraphs.join(" ");
+    *state.temp_audio_path.lock().unwrap() = None;
+    *state.stream.lock().unwrap() = Some(handle);
+
+    state.audio_controller.play().map_err(|e| e.message)
 }
 
 /// Get word timings for the current text
@@ -111,10 +465,54 @@ fn pause_audio(state: State<AppState>) -> Result<(), String> {
 /// Stop audio
 #[tauri::command]
 fn stop_audio(state: State<AppState>) {
+    state.cancel_stream();
     let _ = state.audio_controller.stop();
 }
 
-/// Set playback speed (0.5 - 2.0)
+/// Replace the loaded audio with files played back to back without gaps,
+/// e.g. one per chapter
+#[tauri::command]
+fn queue_audio(paths: Vec<String>, state: State<AppState>) -> Result<(), String> {
+    state.cancel_stream();
+    state.audio_controller.load_queue(paths).map_err(|e| e.message)?;
+    // The previous document's timings don't describe these files
+    state.audio_controller.set_text_map(TextMap::default());
+    Ok(())
+}
+
+/// Add a file to the end of the playback queue
+#[tauri::command]
+fn append_audio_segment(path: String, state: State<AppState>) -> Result<(), String> {
+    state.audio_controller.append_segment(&path).map_err(|e| e.message)
+}
+
+/// Add a file to the playback queue before the entry at `index`
+#[tauri::command]
+fn insert_audio_segment(index: usize, path: String, state: State<AppState>) -> Result<(), String> {
+    state.audio_controller.insert_segment(index, &path).map_err(|e| e.message)
+}
+
+/// Remove an entry from the playback queue; removing the one playing
+/// continues with the next
+#[tauri::command]
+fn remove_audio_segment(index: usize, state: State<AppState>) -> Result<(), String> {
+    state.audio_controller.remove_segment(index).map_err(|e| e.message)
+}
+
+/// Jump to a position in the loaded audio, e.g. a clicked word
+#[tauri::command]
+fn seek_audio(position_ms: u64, state: State<AppState>) -> Result<(), String> {
+    state.audio_controller.seek(position_ms).map_err(|e| e.message)
+}
+
+/// Jump by seconds or by sentences, paragraphs or chapters; negative counts
+/// go back. Returns the new position.
+#[tauri::command]
+fn skip_audio(unit: SkipUnit, count: i32, state: State<AppState>) -> Result<u64, String> {
+    state.audio_controller.skip(unit, count).map_err(|e| e.message)
+}
+
+/// Set playback speed (0.25 - 4.0) without changing pitch
 #[tauri::command]
 fn set_speed(speed: f32, state: State<AppState>) {
     state.audio_controller.set_speed(speed);
@@ -132,12 +530,6 @@ fn get_audio_state(state: State<AppState>) -> AudioState {
     state.audio_controller.get_state()
 }
 
-/// Check if audio playback has finished
-#[tauri::command]
-fn is_audio_finished(state: State<AppState>) -> bool {
-    state.audio_controller.is_finished()
-}
-
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
@@ -145,20 +537,62 @@ pub fn run() {
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_fs::init())
         .manage(AppState::default())
+        .setup(|app| {
+            let config_dir = app.path().app_config_dir()?;
+            let path = settings_file(&config_dir);
+            let state = app.state::<AppState>();
+            let settings = load_settings(&path).unwrap_or_else(|e| {
+                *state.settings_warning.lock().unwrap() = Some(e.message);
+                AppSettings::default()
+            });
+            let cache_dir = app.path().app_cache_dir()?.join("synthesis");
+            *state.cache.lock().unwrap() =
+                Some(Arc::new(SynthesisCache::new(cache_dir, settings.cache_max_bytes())));
+            let app_handle = app.handle().clone();
+            state.audio_controller.set_event_handler(move |event| {
+                let _ = app_handle.emit("playback", event);
+            });
+            state.audio_controller.set_tick_interval(settings.position_tick_ms());
+            state.audio_controller.set_loudness_settings(settings.loudness.clone());
+            // Nothing is loaded yet, so this only records the choice
+            let _ = state.audio_controller.set_output_device(settings.output_device.clone());
+            *state.settings.lock().unwrap() = settings;
+            *state.settings_path.lock().unwrap() = Some(path);
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             greet,
             extract_pdf,
             check_tts_available,
+            get_tts_backends,
             get_voices,
+            get_settings,
+            update_settings,
+            set_default_voice,
+            get_output_devices,
+            set_output_device,
+            validate_tts_config,
+            run_tts_diagnostics,
+            get_piper_workers,
+            get_cache_usage,
+            clear_cache,
             prepare_audio,
+            prepare_ssml,
+            cancel_job,
+            stream_audio,
             get_word_timings,
             play_audio,
             pause_audio,
             stop_audio,
+            queue_audio,
+            append_audio_segment,
+            insert_audio_segment,
+            remove_audio_segment,
+            seek_audio,
+            skip_audio,
             set_speed,
             set_volume,
             get_audio_state,
-            is_audio_finished,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -0,0 +1,426 @@
+use rodio::source::SeekError;
+use rodio::Source;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::wav::{read_wav_samples, write_wav_channels, WavError};
+
+/// Most a document is turned up or down to reach the target
+const MAX_GAIN_DB: f32 = 12.0;
+/// Highest peak normalization may produce without the compressor
+const PEAK_CEILING: f32 = 0.98;
+/// Blocks quieter than this don't count towards loudness
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+/// Blocks this far below the ungated loudness don't count either
+const RELATIVE_GATE_LU: f64 = 10.0;
+
+const COMPRESSOR_THRESHOLD_DB: f32 = -12.0;
+const COMPRESSOR_RATIO: f32 = 2.5;
+const COMPRESSOR_ATTACK_MS: f32 = 5.0;
+const COMPRESSOR_RELEASE_MS: f32 = 120.0;
+
+/// How playback levels are evened out between voices and documents
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LoudnessSettings {
+    /// Turn each document up or down to `target_lufs`
+    pub normalize: bool,
+    pub target_lufs: f32,
+    /// Gently reduce peaks above the threshold
+    pub compressor: bool,
+}
+
+impl Default for LoudnessSettings {
+    fn default() -> Self {
+        LoudnessSettings {
+            normalize: true,
+            target_lufs: -18.0,
+            compressor: false,
+        }
+    }
+}
+
+impl LoudnessSettings {
+    /// Linear gain bringing audio measured as `loudness` to the target.
+    /// Without the compressor the gain is held down so peaks don't clip.
+    pub fn gain(&self, loudness: Option<&Loudness>) -> f32 {
+        let Some(loudness) = loudness.filter(|_| self.normalize) else {
+            return 1.0;
+        };
+        let gain_db = (self.target_lufs - loudness.integrated_lufs).clamp(-MAX_GAIN_DB, MAX_GAIN_DB);
+        let gain = 10f32.powf(gain_db / 20.0);
+        if self.compressor || loudness.peak <= 0.0 {
+            gain
+        } else {
+            gain.min(PEAK_CEILING / loudness.peak)
+        }
+    }
+}
+
+/// Measured level of a piece of audio
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Loudness {
+    /// Gated loudness over the whole audio, in LUFS as in ITU-R BS.1770
+    pub integrated_lufs: f32,
+    /// Largest sample, from 0 to 1
+    pub peak: f32,
+}
+
+/// Second-order IIR section, direct form I
+#[derive(Debug, Clone, Copy, Default)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, input: f64) -> f64 {
+        let output = self.b[0] * input + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+        self.x = [input, self.x[0]];
+        self.y = [output, self.y[0]];
+        output
+    }
+}
+
+/// The K-weighting filter of BS.1770: a high shelf modelling the head,
+/// then a high pass, with coefficients derived for any sample rate
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let rate = sample_rate as f64;
+
+    let k = (std::f64::consts::PI * 1681.974450955533 / rate).tan();
+    let q = 0.7071752369554196;
+    let vh = 10f64.powf(3.999843853973347 / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        ..Default::default()
+    };
+
+    let k = (std::f64::consts::PI * 38.13547087602444 / rate).tan();
+    let q = 0.5003270373238773;
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        ..Default::default()
+    };
+
+    [shelf, high_pass]
+}
+
+/// Integrated loudness measured incrementally, so a stream can be measured
+/// as its chunks arrive
+pub struct LoudnessMeter {
+    channels: usize,
+    filters: Vec<[Biquad; 2]>,
+    /// Samples per channel in each 100 ms step
+    step_len: usize,
+    /// Weighted mean square of each complete step
+    steps: Vec<f64>,
+    step_sum: f64,
+    step_frames: usize,
+    /// Position within the frame being filled
+    channel: usize,
+    peak: f32,
+}
+
+impl LoudnessMeter {
+    pub fn new(channels: u16, sample_rate: u32) -> Self {
+        let channels = channels.max(1) as usize;
+        LoudnessMeter {
+            channels,
+            filters: vec![k_weighting(sample_rate); channels],
+            step_len: (sample_rate as usize / 10).max(1),
+            steps: Vec::new(),
+            step_sum: 0.0,
+            step_frames: 0,
+            channel: 0,
+            peak: 0.0,
+        }
+    }
+
+    /// Add interleaved samples
+    pub fn add(&mut self, samples: &[i16]) {
+        for &sample in samples {
+            let value = sample as f64 / 32768.0;
+            self.peak = self.peak.max(value.abs() as f32);
+            let [shelf, high_pass] = &mut self.filters[self.channel];
+            let weighted = high_pass.process(shelf.process(value));
+            self.step_sum += weighted * weighted;
+
+            self.channel += 1;
+            if self.channel == self.channels {
+                self.channel = 0;
+                self.step_frames += 1;
+                if self.step_frames == self.step_len {
+                    self.steps.push(self.step_sum / self.step_len as f64);
+                    self.step_sum = 0.0;
+                    self.step_frames = 0;
+                }
+            }
+        }
+    }
+
+    /// Gated loudness of everything added so far, over 400 ms blocks that
+    /// overlap by three quarters. `None` until there is a block above the
+    /// absolute gate.
+    pub fn loudness(&self) -> Option<Loudness> {
+        let to_lufs = |power: f64| -0.691 + 10.0 * power.log10();
+        let blocks: Vec<f64> = self
+            .steps
+            .windows(4)
+            .map(|steps| steps.iter().sum::<f64>() / 4.0)
+            .filter(|&power| to_lufs(power) > ABSOLUTE_GATE_LUFS)
+            .collect();
+        if blocks.is_empty() {
+            return None;
+        }
+        let ungated = blocks.iter().sum::<f64>() / blocks.len() as f64;
+        let gate = to_lufs(ungated) - RELATIVE_GATE_LU;
+        let gated: Vec<f64> = blocks.into_iter().filter(|&power| to_lufs(power) > gate).collect();
+        let power = gated.iter().sum::<f64>() / gated.len() as f64;
+        Some(Loudness {
+            integrated_lufs: to_lufs(power) as f32,
+            peak: self.peak,
+        })
+    }
+}
+
+/// Normalize and compress a 16-bit PCM WAV file in place as playback
+/// would, returning the level of the result. Silent files are left alone.
+pub fn level_wav(path: &Path, settings: &LoudnessSettings) -> Result<Option<Loudness>, WavError> {
+    let (spec, samples) = read_wav_samples(path)?;
+    let mut meter = LoudnessMeter::new(spec.channels, spec.sample_rate);
+    meter.add(&samples);
+    let Some(loudness) = meter.loudness() else {
+        return Ok(None);
+    };
+    let gain = settings.gain(Some(&loudness));
+    if gain == 1.0 && !settings.compressor {
+        return Ok(Some(loudness));
+    }
+
+    let control = LevelControl::default();
+    control.set(gain, settings.compressor);
+    let source = rodio::buffer::SamplesBuffer::new(spec.channels, spec.sample_rate, samples);
+    let leveled: Vec<i16> = Leveled::new(source, control).collect();
+    write_wav_channels(path, spec.channels, spec.sample_rate, &leveled)?;
+
+    let mut meter = LoudnessMeter::new(spec.channels, spec.sample_rate);
+    meter.add(&leveled);
+    Ok(meter.loudness())
+}
+
+/// Gain and compression shared between the audio thread and the sources it
+/// has queued, so changes apply to audio already in the sink
+#[derive(Debug, Clone)]
+pub struct LevelControl {
+    gain: Arc<AtomicU32>,
+    compressor: Arc<AtomicBool>,
+}
+
+impl Default for LevelControl {
+    fn default() -> Self {
+        LevelControl {
+            gain: Arc::new(AtomicU32::new(1f32.to_bits())),
+            compressor: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+impl LevelControl {
+    pub fn gain(&self) -> f32 {
+        f32::from_bits(self.gain.load(Ordering::Relaxed))
+    }
+
+    pub fn set(&self, gain: f32, compressor: bool) {
+        self.gain.store(gain.to_bits(), Ordering::Relaxed);
+        self.compressor.store(compressor, Ordering::Relaxed);
+    }
+}
+
+/// Applies normalization gain and the optional compressor to a source
+pub struct Leveled<S> {
+    inner: S,
+    control: LevelControl,
+    channels: usize,
+    frame: Vec<f32>,
+    frame_pos: usize,
+    /// Peak envelope followed by the compressor
+    envelope: f32,
+    attack: f32,
+    release: f32,
+}
+
+impl<S> Leveled<S>
+where
+    S: Source<Item = i16>,
+{
+    pub fn new(inner: S, control: LevelControl) -> Self {
+        let channels = inner.channels().max(1) as usize;
+        let coefficient = |ms: f32| (-1.0 / (ms / 1000.0 * inner.sample_rate().max(1) as f32)).exp();
+        Leveled {
+            channels,
+            control,
+            frame: Vec::with_capacity(channels),
+            frame_pos: 0,
+            envelope: 0.0,
+            attack: coefficient(COMPRESSOR_ATTACK_MS),
+            release: coefficient(COMPRESSOR_RELEASE_MS),
+            inner,
+        }
+    }
+
+    /// Read and process the next frame; false at the end of the source
+    fn next_frame(&mut self) -> bool {
+        self.frame.clear();
+        self.frame_pos = 0;
+        let gain = self.control.gain();
+        for _ in 0..self.channels {
+            match self.inner.next() {
+                Some(sample) => self.frame.push(sample as f32 / 32768.0 * gain),
+                None => break,
+            }
+        }
+        if self.frame.is_empty() {
+            return false;
+        }
+
+        if self.control.compressor.load(Ordering::Relaxed) {
+            let level = self.frame.iter().fold(0f32, |peak, s| peak.max(s.abs()));
+            let coefficient = if level > self.envelope {
+                self.attack
+            } else {
+                self.release
+            };
+            self.envelope = coefficient * self.envelope + (1.0 - coefficient) * level;
+            let over_db = 20.0 * self.envelope.max(1e-6).log10() - COMPRESSOR_THRESHOLD_DB;
+            if over_db > 0.0 {
+                let reduction = 10f32.powf(-over_db * (1.0 - 1.0 / COMPRESSOR_RATIO) / 20.0);
+                self.frame.iter_mut().for_each(|s| *s *= reduction);
+            }
+        }
+        true
+    }
+}
+
+impl<S> Iterator for Leveled<S>
+where
+    S: Source<Item = i16>,
+{
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        if self.frame_pos == self.frame.len() && !self.next_frame() {
+            return None;
+        }
+        let sample = self.frame[self.frame_pos];
+        self.frame_pos += 1;
+        Some((sample * 32768.0).round().clamp(i16::MIN as f32, i16::MAX as f32) as i16)
+    }
+}
+
+impl<S> Source for Leveled<S>
+where
+    S: Source<Item = i16>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels as u16
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.inner.try_seek(pos)?;
+        self.frame.clear();
+        self.frame_pos = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tone(amplitude: f32, seconds: usize) -> Vec<i16> {
+        (0..48000 * seconds)
+            .map(|i| {
+                let phase = i as f32 * 997.0 * 2.0 * std::f32::consts::PI / 48000.0;
+                (phase.sin() * amplitude * 32767.0) as i16
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_measures_loudness_and_gain() {
+        // A half-scale 997 Hz tone measures about -9 LUFS
+        let mut meter = LoudnessMeter::new(1, 48000);
+        meter.add(&tone(0.5, 3));
+        let loudness = meter.loudness().unwrap();
+        assert!((loudness.integrated_lufs + 9.0).abs() < 0.2, "{}", loudness.integrated_lufs);
+        assert!((loudness.peak - 0.5).abs() < 0.01);
+
+        // Silence after it is gated out rather than lowering the result
+        meter.add(&vec![0; 48000 * 3]);
+        let gated = meter.loudness().unwrap();
+        assert!((gated.integrated_lufs - loudness.integrated_lufs).abs() < 0.5);
+        assert_eq!(LoudnessMeter::new(1, 48000).loudness(), None);
+
+        let settings = LoudnessSettings::default();
+        let quiet = Loudness {
+            integrated_lufs: -24.0,
+            peak: 0.1,
+        };
+        assert!((settings.gain(Some(&quiet)) - 2.0).abs() < 0.01);
+        // Held below clipping unless the compressor handles the peaks
+        let peaky = Loudness {
+            integrated_lufs: -24.0,
+            peak: 0.8,
+        };
+        assert!((settings.gain(Some(&peaky)) - PEAK_CEILING / 0.8).abs() < 0.01);
+        let compressed = LoudnessSettings {
+            compressor: true,
+            ..Default::default()
+        };
+        assert!((compressed.gain(Some(&peaky)) - 2.0).abs() < 0.01);
+        assert_eq!(settings.gain(None), 1.0);
+    }
+
+    #[test]
+    fn test_level_wav_reaches_target() {
+        let path = std::env::temp_dir().join(format!("loudness_level_{}.wav", std::process::id()));
+        // About -27 LUFS, so normalization turns it up by 9 dB
+        write_wav_channels(&path, 1, 48000, &tone(0.18, 3)).unwrap();
+        let leveled = level_wav(&path, &LoudnessSettings::default()).unwrap().unwrap();
+        let (_, samples) = read_wav_samples(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut meter = LoudnessMeter::new(1, 48000);
+        meter.add(&samples);
+
+        assert!((leveled.integrated_lufs + 18.0).abs() < 0.2, "{}", leveled.integrated_lufs);
+        assert_eq!(meter.loudness(), Some(leveled));
+    }
+}
@@ -0,0 +1,171 @@
+use std::f32::consts::PI;
+use std::path::Path;
+
+use crate::tts_engine::{
+    BackendKind, RawAudio, TtsBackend, TtsError, TtsErrorKind, TtsResult, VoiceInfo, VoiceProfile,
+    WordTiming,
+};
+use crate::wav::{ms_to_samples, write_wav};
+
+/// 16 kHz keeps every millisecond a whole number of samples
+pub const MOCK_SAMPLE_RATE: u32 = 16000;
+const MOCK_VOICE_ID: &str = "mock";
+const MS_PER_CHAR: u64 = 40;
+const MIN_WORD_MS: u64 = 120;
+const WORD_GAP_MS: u64 = 40;
+const DEFAULT_SENTENCE_SILENCE_MS: u64 = 200;
+
+/// Deterministic engine for tests: each word is a tone whose length depends
+/// only on the word, separated by silence, so timings are known exactly
+pub struct MockBackend;
+
+impl MockBackend {
+    pub fn new() -> Self {
+        MockBackend
+    }
+}
+
+/// Exact word timings and total duration the mock engine produces for `text`
+pub fn mock_word_timings(text: &str, profile: &VoiceProfile) -> (Vec<WordTiming>, u64) {
+    let length_scale = profile.length_scale.unwrap_or(1.0) as f64;
+    let scale = |ms: u64| (ms as f64 * length_scale).round() as u64;
+    let sentence_silence_ms = profile
+        .sentence_silence
+        .map(|s| (s as f64 * 1000.0).round() as u64)
+        .unwrap_or(DEFAULT_SENTENCE_SILENCE_MS);
+
+    let mut timings = Vec::new();
+    let mut current_ms = 0u64;
+
+    for word in text.split_whitespace() {
+        let duration = scale((word.chars().count() as u64 * MS_PER_CHAR).max(MIN_WORD_MS));
+        timings.push(WordTiming {
+            word: word.to_string(),
+            start_ms: current_ms,
+            end_ms: current_ms + duration,
+        });
+        current_ms += duration + scale(WORD_GAP_MS);
+        if word.ends_with(['.', '!', '?']) {
+            current_ms += sentence_silence_ms;
+        }
+    }
+
+    (timings, current_ms)
+}
+
+/// Render timings as tones over silence
+fn render(timings: &[WordTiming], duration_ms: u64) -> Vec<i16> {
+    let mut samples = vec![0i16; ms_to_samples(duration_ms, MOCK_SAMPLE_RATE)];
+
+    for (index, timing) in timings.iter().enumerate() {
+        // Cycle through a few pitches so neighbouring words are distinguishable
+        let frequency = 220.0 + 110.0 * (index % 5) as f32;
+        let start = ms_to_samples(timing.start_ms, MOCK_SAMPLE_RATE);
+        let end = ms_to_samples(timing.end_ms, MOCK_SAMPLE_RATE).min(samples.len());
+        for (n, sample) in samples[start..end].iter_mut().enumerate() {
+            let t = n as f32 / MOCK_SAMPLE_RATE as f32;
+            *sample = ((2.0 * PI * frequency * t).sin() * 0.3 * i16::MAX as f32) as i16;
+        }
+    }
+
+    samples
+}
+
+impl TtsBackend for MockBackend {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Mock
+    }
+
+    fn is_available(&self) -> bool {
+        true
+    }
+
+    fn list_voices(&self) -> Vec<VoiceInfo> {
+        vec![VoiceInfo {
+            id: MOCK_VOICE_ID.to_string(),
+            name: "Mock tones".to_string(),
+            language: "en-US".to_string(),
+            quality: None,
+            sample_rate: Some(MOCK_SAMPLE_RATE),
+            speakers: Vec::new(),
+            model_path: String::new(),
+            available: true,
+        }]
+    }
+
+    fn synthesize(
+        &self,
+        text: &str,
+        output_path: &str,
+        profile: &VoiceProfile,
+    ) -> Result<TtsResult, TtsError> {
+        let (word_timings, duration_ms) = mock_word_timings(text, profile);
+        write_wav(Path::new(output_path), MOCK_SAMPLE_RATE, &render(&word_timings, duration_ms))
+            .map_err(|e| TtsError::new(TtsErrorKind::Io, e.message))?;
+
+        Ok(TtsResult {
+            audio_path: output_path.to_string(),
+            word_timings,
+            duration_ms,
+        })
+    }
+
+    fn sample_rate(&self, _profile: &VoiceProfile) -> Option<u32> {
+        Some(MOCK_SAMPLE_RATE)
+    }
+
+    fn synthesize_raw(&self, text: &str, profile: &VoiceProfile) -> Result<RawAudio, TtsError> {
+        let (word_timings, duration_ms) = mock_word_timings(text, profile);
+        Ok(RawAudio {
+            sample_rate: MOCK_SAMPLE_RATE,
+            channels: 1,
+            samples: render(&word_timings, duration_ms),
+            word_timings,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tts_engine::{create_backend, TtsConfig};
+
+    fn temp_wav(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("{}_{}.wav", name, std::process::id()))
+            .display()
+            .to_string()
+    }
+
+    #[test]
+    fn test_mock_word_timings_are_exact() {
+        let (timings, duration_ms) = mock_word_timings("Hi there. Bye", &VoiceProfile::default());
+        let spans: Vec<(u64, u64)> = timings.iter().map(|t| (t.start_ms, t.end_ms)).collect();
+        // "Hi" 120, gap 40, "there." 240, gap 40 + sentence 200, "Bye" 120, gap 40
+        assert_eq!(spans, vec![(0, 120), (160, 400), (640, 760)]);
+        assert_eq!(duration_ms, 800);
+    }
+
+    #[test]
+    fn test_mock_synthesis_matches_timings() {
+        let backend = create_backend(BackendKind::Mock, &TtsConfig::default());
+        let path = temp_wav("mock_synthesis");
+        let profile = VoiceProfile {
+            length_scale: Some(1.5),
+            ..Default::default()
+        };
+
+        let result = backend
+            .synthesize("The quick brown fox jumps over the lazy dog.", &path, &profile)
+            .unwrap();
+        let reader = hound::WavReader::open(&path).unwrap();
+        let (spec, frames) = (reader.spec(), reader.duration());
+        drop(reader);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(spec.sample_rate, MOCK_SAMPLE_RATE);
+        assert_eq!(frames as usize, ms_to_samples(result.duration_ms, MOCK_SAMPLE_RATE));
+        assert_eq!(result.word_timings.len(), 9);
+        assert_eq!(result.word_timings[0].end_ms, 180);
+    }
+}
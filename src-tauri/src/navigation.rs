@@ -0,0 +1,182 @@
+use serde::{Deserialize, Serialize};
+
+use crate::pauses::{block_kind, BlockKind};
+use crate::synthesis::split_sentences;
+use crate::tts_engine::WordTiming;
+
+/// Going back within this much of a unit's start goes to the previous unit
+/// rather than restarting the current one
+const RESTART_GRACE_MS: u64 = 1500;
+
+/// What a relative jump is measured in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipUnit {
+    Seconds,
+    Sentence,
+    Paragraph,
+    Chapter,
+}
+
+/// Where each sentence, paragraph and chapter starts in the document, and
+/// when each word is spoken
+#[derive(Debug, Clone, Default)]
+pub struct TextMap {
+    timings: Vec<WordTiming>,
+    /// Word indices at which each unit starts, ascending and starting at 0
+    sentence_starts: Vec<usize>,
+    paragraph_starts: Vec<usize>,
+    chapter_starts: Vec<usize>,
+}
+
+impl TextMap {
+    /// Map the document's structure. Timings are added as audio becomes
+    /// available and must follow the paragraphs' words one to one.
+    pub fn new(paragraphs: &[String], timings: Vec<WordTiming>) -> Self {
+        let mut map = TextMap {
+            timings,
+            sentence_starts: vec![0],
+            paragraph_starts: vec![0],
+            chapter_starts: vec![0],
+        };
+        let kinds: Vec<BlockKind> = paragraphs.iter().map(|p| block_kind(p)).collect();
+        // Documents without chapter headings are navigated by headings
+        let chapter_kinds: &[BlockKind] = if kinds.contains(&BlockKind::Chapter) {
+            &[BlockKind::Chapter]
+        } else {
+            &[BlockKind::Chapter, BlockKind::Heading]
+        };
+
+        let mut word = 0;
+        for (paragraph, kind) in paragraphs.iter().zip(&kinds) {
+            let words = paragraph.split_whitespace().count();
+            if words == 0 {
+                continue;
+            }
+            map.paragraph_starts.push(word);
+            if chapter_kinds.contains(kind) {
+                map.chapter_starts.push(word);
+            }
+            for sentence in split_sentences(paragraph) {
+                map.sentence_starts.push(word);
+                word += sentence.split_whitespace().count();
+            }
+        }
+
+        for starts in [
+            &mut map.sentence_starts,
+            &mut map.paragraph_starts,
+            &mut map.chapter_starts,
+        ] {
+            starts.dedup();
+        }
+        map
+    }
+
+    /// Add timings for the next words, e.g. as a stream synthesizes them
+    pub fn extend_timings(&mut self, timings: &[WordTiming]) {
+        self.timings.extend_from_slice(timings);
+    }
+
+    /// Index of the word being spoken at `position_ms`, or `None` before
+    /// the first word
+    pub fn word_at(&self, position_ms: u64) -> Option<usize> {
+        self.timings
+            .partition_point(|t| t.start_ms <= position_ms)
+            .checked_sub(1)
+    }
+
+    fn starts(&self, unit: SkipUnit) -> &[usize] {
+        match unit {
+            SkipUnit::Seconds | SkipUnit::Sentence => &self.sentence_starts,
+            SkipUnit::Paragraph => &self.paragraph_starts,
+            SkipUnit::Chapter => &self.chapter_starts,
+        }
+    }
+
+    /// Position `count` units away from `position_ms`; negative counts go
+    /// back. The first step back restarts the current unit unless playback
+    /// is just past its start. `None` if the target hasn't been synthesized
+    /// yet or lies beyond the end.
+    pub fn skip_target(&self, position_ms: u64, unit: SkipUnit, count: i32) -> Option<u64> {
+        if unit == SkipUnit::Seconds {
+            let target = position_ms as i64 + count as i64 * 1000;
+            return Some(target.max(0) as u64);
+        }
+        if self.timings.is_empty() {
+            return None;
+        }
+
+        let word = self
+            .timings
+            .partition_point(|t| t.start_ms <= position_ms)
+            .saturating_sub(1);
+        let starts = self.starts(unit);
+        let current = starts.partition_point(|&start| start <= word).saturating_sub(1);
+
+        let target = if count < 0 {
+            let unit_start_ms = self.timings[starts[current]].start_ms;
+            let mut steps = count.unsigned_abs() as usize;
+            if position_ms.saturating_sub(unit_start_ms) > RESTART_GRACE_MS {
+                steps -= 1;
+            }
+            starts[current.saturating_sub(steps)]
+        } else {
+            *starts.get(current + count as usize)?
+        };
+
+        self.timings.get(target).map(|t| t.start_ms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Each word lasts one second
+    fn map(paragraphs: &[&str]) -> TextMap {
+        let paragraphs: Vec<String> = paragraphs.iter().map(|p| p.to_string()).collect();
+        let timings = paragraphs
+            .join(" ")
+            .split_whitespace()
+            .enumerate()
+            .map(|(i, word)| WordTiming {
+                word: word.to_string(),
+                start_ms: i as u64 * 1000,
+                end_ms: i as u64 * 1000 + 900,
+            })
+            .collect();
+        TextMap::new(&paragraphs, timings)
+    }
+
+    #[test]
+    fn test_skip_by_text_units() {
+        // Words: 0-1 heading, 2-4 and 5-6 sentences, 7-8 heading, 9-10
+        let map = map(&[
+            "Chapter One",
+            "It was late. Nobody noticed.",
+            "Chapter Two",
+            "Morning came.",
+        ]);
+
+        // Well into "Nobody noticed." (word 6): back restarts the sentence,
+        // back twice reaches the one before
+        assert_eq!(map.skip_target(6600, SkipUnit::Sentence, -1), Some(5000));
+        assert_eq!(map.skip_target(6600, SkipUnit::Sentence, -2), Some(2000));
+        // Just after its start, back goes to the previous sentence
+        assert_eq!(map.skip_target(5200, SkipUnit::Sentence, -1), Some(2000));
+        assert_eq!(map.skip_target(5200, SkipUnit::Sentence, 1), Some(7000));
+
+        assert_eq!(map.skip_target(3000, SkipUnit::Paragraph, 1), Some(7000));
+        assert_eq!(map.skip_target(3000, SkipUnit::Chapter, 1), Some(7000));
+        assert_eq!(map.skip_target(9500, SkipUnit::Chapter, -1), Some(7000));
+        assert_eq!(map.skip_target(7500, SkipUnit::Chapter, -1), Some(0));
+        assert_eq!(map.skip_target(9500, SkipUnit::Chapter, 1), None);
+
+        assert_eq!(map.word_at(6600), Some(6));
+        assert_eq!(TextMap::default().word_at(6600), None);
+
+        assert_eq!(map.skip_target(9500, SkipUnit::Seconds, -15), Some(0));
+        assert_eq!(map.skip_target(9500, SkipUnit::Seconds, 15), Some(24500));
+    }
+}
@@ -0,0 +1,172 @@
+use serde::{Deserialize, Serialize};
+
+use crate::dialogue::quote_open_after;
+use crate::synthesis::{split_into_chunks, TextChunk};
+
+/// Paragraphs with at most this many words and no closing punctuation are
+/// read as headings
+const MAX_HEADING_WORDS: usize = 12;
+/// First words that mark a heading as the start of a chapter
+const CHAPTER_WORDS: [&str; 5] = ["chapter", "part", "book", "prologue", "epilogue"];
+
+/// Silence inserted around the document's structure, in milliseconds
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PausePolicy {
+    /// After each sentence, within a paragraph
+    pub sentence_ms: u64,
+    /// Between paragraphs
+    pub paragraph_ms: u64,
+    /// Before and after headings
+    pub heading_ms: u64,
+    /// Before a chapter heading
+    pub chapter_ms: u64,
+}
+
+impl Default for PausePolicy {
+    fn default() -> Self {
+        PausePolicy {
+            sentence_ms: 300,
+            paragraph_ms: 800,
+            heading_ms: 1200,
+            chapter_ms: 2000,
+        }
+    }
+}
+
+/// What a paragraph is within the document's structure
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockKind {
+    Paragraph,
+    Heading,
+    Chapter,
+}
+
+/// Guess whether a paragraph is body text, a heading or a chapter heading.
+/// PDF text carries no structure, so headings are recognised as short lines
+/// that don't end like a sentence.
+pub fn block_kind(paragraph: &str) -> BlockKind {
+    let words: Vec<&str> = paragraph.split_whitespace().collect();
+    let Some(first) = words.first() else {
+        return BlockKind::Paragraph;
+    };
+    if words.len() > MAX_HEADING_WORDS {
+        return BlockKind::Paragraph;
+    }
+
+    let first = first
+        .trim_matches(|c: char| !c.is_alphanumeric())
+        .to_lowercase();
+    if CHAPTER_WORDS.contains(&first.as_str()) {
+        BlockKind::Chapter
+    } else if paragraph.trim_end().ends_with(['.', '!', '?', ',', ';', ':', '"', '\u{201d}']) {
+        BlockKind::Paragraph
+    } else {
+        BlockKind::Heading
+    }
+}
+
+impl PausePolicy {
+    /// Silence before a paragraph of kind `kind` that follows one of kind
+    /// `previous`
+    pub fn between(&self, previous: BlockKind, kind: BlockKind) -> u64 {
+        match (previous, kind) {
+            (_, BlockKind::Chapter) => self.chapter_ms,
+            (_, BlockKind::Heading) | (BlockKind::Heading | BlockKind::Chapter, _) => self.heading_ms,
+            (BlockKind::Paragraph, BlockKind::Paragraph) => self.paragraph_ms,
+        }
+    }
+}
+
+/// Split paragraphs into chunks of at most `max_chars` and set the pause
+/// before each one. Chunks continuing a paragraph get no extra pause: they
+/// start at a sentence boundary, where the engine already adds
+/// `sentence_ms`. Chunks that start inside a quotation are marked so the
+/// quotation keeps its voice.
+pub fn plan_chunks(paragraphs: &[String], max_chars: usize, policy: &PausePolicy) -> Vec<TextChunk> {
+    let kinds: Vec<BlockKind> = paragraphs.iter().map(|p| block_kind(p)).collect();
+    let mut chunks = split_into_chunks(paragraphs, max_chars);
+
+    let mut previous_paragraph: Option<usize> = None;
+    let mut quote_open = false;
+    for chunk in chunks.iter_mut() {
+        chunk.pause_ms = match previous_paragraph {
+            Some(previous) if previous != chunk.paragraph => {
+                policy.between(kinds[previous], kinds[chunk.paragraph])
+            }
+            _ => 0,
+        };
+        // A quotation running on into the next paragraph is reopened there
+        // with a fresh mark, so each paragraph starts outside one
+        if previous_paragraph != Some(chunk.paragraph) {
+            quote_open = false;
+        }
+        chunk.quote_open = quote_open;
+        quote_open = quote_open_after(&chunk.text, quote_open);
+        previous_paragraph = Some(chunk.paragraph);
+    }
+
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_kind() {
+        assert_eq!(block_kind("Chapter 3: The Storm"), BlockKind::Chapter);
+        assert_eq!(block_kind("PROLOGUE"), BlockKind::Chapter);
+        assert_eq!(block_kind("A Quiet Morning"), BlockKind::Heading);
+        assert_eq!(block_kind("She left."), BlockKind::Paragraph);
+        assert_eq!(
+            block_kind("Part of the reason was that nobody had thought to ask the people who lived there"),
+            BlockKind::Paragraph
+        );
+    }
+
+    #[test]
+    fn test_plan_chunks_sets_pauses() {
+        let paragraphs: Vec<String> = [
+            "Chapter One",
+            "It was late. The rain had stopped.",
+            "Nobody noticed.",
+            "Interlude",
+            "Later still.",
+            "Chapter Two",
+        ]
+        .iter()
+        .map(|p| p.to_string())
+        .collect();
+        let policy = PausePolicy::default();
+
+        let chunks = plan_chunks(&paragraphs, 15, &policy);
+        let pauses: Vec<(usize, u64)> = chunks.iter().map(|c| (c.paragraph, c.pause_ms)).collect();
+        assert_eq!(
+            pauses,
+            vec![
+                (0, 0),
+                (1, policy.heading_ms),
+                (1, 0),
+                (2, policy.paragraph_ms),
+                (3, policy.heading_ms),
+                (4, policy.heading_ms),
+                (5, policy.chapter_ms),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plan_chunks_carries_quotes() {
+        let paragraphs = vec![
+            "\"It was late. The rain had stopped.\" She left.".to_string(),
+            "\"Nobody noticed. Not even me.".to_string(),
+            "\"Or you.\"".to_string(),
+        ];
+        let chunks = plan_chunks(&paragraphs, 15, &PausePolicy::default());
+        let open: Vec<bool> = chunks.iter().map(|c| c.quote_open).collect();
+        assert_eq!(open, vec![false, true, false, false, true, false]);
+        assert_eq!(chunks[1].spoken_text(), "\u{201c}The rain had stopped.\"");
+    }
+}
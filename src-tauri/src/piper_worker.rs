@@ -0,0 +1,414 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::jobs::CancelToken;
+use crate::tts_engine::{classify_engine_error, synthesis_timeout, TtsError, TtsErrorKind};
+
+/// Lines of Piper's stderr kept for error messages
+const STDERR_TAIL_LINES: usize = 20;
+/// Idle processes kept per voice; extra ones are shut down when returned
+const MAX_IDLE_PER_KEY: usize = 8;
+/// Attempts per request when Piper crashes or hangs
+const MAX_ATTEMPTS: u32 = 3;
+/// Wait before the first retry, doubled for each one after
+const RETRY_BACKOFF: Duration = Duration::from_millis(250);
+
+/// Workers are interchangeable when they run the same executable, model and
+/// synthesis flags. The speaker is chosen per request.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct WorkerKey {
+    pub program: PathBuf,
+    pub model: String,
+    pub args: Vec<String>,
+}
+
+/// A long-lived Piper process reading one JSON request per line and
+/// answering with the path of the WAV it wrote
+pub struct PiperWorker {
+    /// Shared so a cancel can kill the process while a request is blocked
+    child: Arc<Mutex<Child>>,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    stderr_tail: Arc<Mutex<VecDeque<String>>>,
+    stderr_thread: Option<JoinHandle<()>>,
+}
+
+impl PiperWorker {
+    pub fn spawn(key: &WorkerKey) -> Result<Self, TtsError> {
+        let mut child = Command::new(&key.program)
+            .args(["--model", key.model.as_str()])
+            .args(&key.args)
+            .arg("--json-input")
+            .arg("--output_dir")
+            .arg(std::env::temp_dir())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| {
+                TtsError::new(TtsErrorKind::EngineNotFound, format!("Failed to start Piper: {}", e))
+            })?;
+
+        let stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| TtsError::new(TtsErrorKind::Crashed, "Piper stdin unavailable"))?;
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| TtsError::new(TtsErrorKind::Crashed, "Piper stdout unavailable"))?;
+
+        // Drain stderr so Piper never blocks on a full pipe, keeping the tail
+        let stderr_tail = Arc::new(Mutex::new(VecDeque::new()));
+        let stderr_thread = child.stderr.take().map(|stderr| {
+            let tail = stderr_tail.clone();
+            thread::spawn(move || {
+                for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                    let mut tail = tail.lock().unwrap();
+                    if tail.len() == STDERR_TAIL_LINES {
+                        tail.pop_front();
+                    }
+                    tail.push_back(line);
+                }
+            })
+        });
+
+        Ok(PiperWorker {
+            child: Arc::new(Mutex::new(child)),
+            stdin,
+            stdout: BufReader::new(stdout),
+            stderr_tail,
+            stderr_thread,
+        })
+    }
+
+    /// Whether the process is still running
+    pub fn is_alive(&mut self) -> bool {
+        matches!(self.child.lock().unwrap().try_wait(), Ok(None))
+    }
+
+    /// Error for a request that Piper didn't complete. Once the process has
+    /// exited its stderr is read to the end, so the cause is in the tail.
+    fn failure(&mut self, what: String) -> TtsError {
+        if !self.is_alive() {
+            if let Some(thread) = self.stderr_thread.take() {
+                let _ = thread.join();
+            }
+        }
+        let stderr = self.stderr_tail();
+        let kind = match classify_engine_error(&stderr) {
+            TtsErrorKind::Other => TtsErrorKind::Crashed,
+            kind => kind,
+        };
+        TtsError::new(kind, format!("{}: {}", what, stderr))
+    }
+
+    fn stderr_tail(&self) -> String {
+        self.stderr_tail
+            .lock()
+            .unwrap()
+            .iter()
+            .cloned()
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Synthesize one line of text into `output_path`, killing the process
+    /// if it takes longer than `timeout`
+    pub fn synthesize(
+        &mut self,
+        text: &str,
+        output_path: &Path,
+        speaker_id: Option<u32>,
+        timeout: Duration,
+    ) -> Result<(), TtsError> {
+        // Piper reads one utterance per line
+        let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        let mut request = serde_json::json!({
+            "text": text,
+            "output_file": output_path,
+        });
+        if let Some(speaker) = speaker_id {
+            request["speaker_id"] = speaker.into();
+        }
+        // A file left over from an earlier run would pass for Piper's output
+        let _ = std::fs::remove_file(output_path);
+
+        if let Err(e) = writeln!(self.stdin, "{}", request).and_then(|_| self.stdin.flush()) {
+            return Err(self.failure(format!("Failed to write to Piper stdin: {}", e)));
+        }
+
+        // Watchdog: kill Piper if no answer arrives in time. Dropping
+        // `answered` when the read returns stops it. `done` is set and
+        // checked under the child lock, so an answer that arrives just as
+        // the time runs out is never reported as a timeout.
+        let (answered, watch) = mpsc::channel::<()>();
+        let timed_out = Arc::new(AtomicBool::new(false));
+        let done = Arc::new(AtomicBool::new(false));
+        {
+            let child = self.child.clone();
+            let timed_out = timed_out.clone();
+            let done = done.clone();
+            thread::spawn(move || {
+                if let Err(RecvTimeoutError::Timeout) = watch.recv_timeout(timeout) {
+                    let mut child = child.lock().unwrap();
+                    if !done.load(Ordering::SeqCst) {
+                        timed_out.store(true, Ordering::SeqCst);
+                        let _ = child.kill();
+                    }
+                }
+            });
+        }
+
+        let mut line = String::new();
+        let read = self.stdout.read_line(&mut line);
+        {
+            let _child = self.child.lock().unwrap();
+            done.store(true, Ordering::SeqCst);
+        }
+        drop(answered);
+
+        if timed_out.load(Ordering::SeqCst) {
+            return Err(TtsError::new(
+                TtsErrorKind::Timeout,
+                format!("Piper took longer than {} s", timeout.as_secs()),
+            ));
+        }
+        match read {
+            Err(e) => return Err(self.failure(format!("Failed to read from Piper: {}", e))),
+            Ok(0) => return Err(self.failure("Piper exited unexpectedly".to_string())),
+            Ok(_) => {}
+        }
+        if !output_path.exists() {
+            return Err(self.failure(format!("Piper did not write {}", output_path.display())));
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for PiperWorker {
+    fn drop(&mut self) {
+        let mut child = self.child.lock().unwrap();
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+}
+
+/// Sleep before a retry, waking early if the request is cancelled
+fn backoff(delay: Duration, cancel: &CancelToken) {
+    let step = Duration::from_millis(25);
+    let mut waited = Duration::ZERO;
+    while waited < delay && !cancel.is_cancelled() {
+        thread::sleep(step);
+        waited += step;
+    }
+}
+
+/// Number of idle workers per voice model
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkerStatus {
+    pub model: String,
+    pub idle: usize,
+}
+
+/// Idle Piper workers, checked out for one request at a time so concurrent
+/// synthesis gets one process per thread
+pub struct WorkerPool {
+    idle: Mutex<HashMap<WorkerKey, Vec<PiperWorker>>>,
+}
+
+impl WorkerPool {
+    pub fn new() -> Self {
+        WorkerPool {
+            idle: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The pool shared by every Piper backend in the process
+    pub fn global() -> &'static WorkerPool {
+        static POOL: OnceLock<WorkerPool> = OnceLock::new();
+        POOL.get_or_init(WorkerPool::new)
+    }
+
+    /// Take a live idle worker, or start a new one
+    fn checkout(&self, key: &WorkerKey) -> Result<PiperWorker, TtsError> {
+        let mut idle = self.idle.lock().unwrap();
+        if let Some(workers) = idle.get_mut(key) {
+            while let Some(mut worker) = workers.pop() {
+                if worker.is_alive() {
+                    return Ok(worker);
+                }
+            }
+        }
+        drop(idle);
+        PiperWorker::spawn(key)
+    }
+
+    fn checkin(&self, key: &WorkerKey, worker: PiperWorker) {
+        let mut idle = self.idle.lock().unwrap();
+        let workers = idle.entry(key.clone()).or_default();
+        if workers.len() < MAX_IDLE_PER_KEY {
+            workers.push(worker);
+        }
+    }
+
+    /// Synthesize on a pooled worker. If Piper crashes or hangs the worker is
+    /// replaced and the request retried with backoff; setup problems such as
+    /// a missing model fail straight away. Cancelling `cancel` kills the
+    /// worker mid-request.
+    pub fn synthesize(
+        &self,
+        key: &WorkerKey,
+        text: &str,
+        output_path: &Path,
+        speaker_id: Option<u32>,
+        cancel: &CancelToken,
+    ) -> Result<(), TtsError> {
+        let timeout = synthesis_timeout(text);
+        let mut attempt = 0;
+        loop {
+            if cancel.is_cancelled() {
+                return Err(cancel.error());
+            }
+            let mut worker = self.checkout(key)?;
+            let child = worker.child.clone();
+            let error = match cancel.while_running(&child, || {
+                worker.synthesize(text, output_path, speaker_id, timeout)
+            }) {
+                Ok(()) => {
+                    self.checkin(key, worker);
+                    return Ok(());
+                }
+                Err(_) if cancel.is_cancelled() => return Err(cancel.error()),
+                Err(e) => e,
+            };
+            // The failed worker is dropped, killing its process
+            drop(worker);
+
+            attempt += 1;
+            if attempt >= MAX_ATTEMPTS || !error.kind.is_transient() {
+                return Err(error);
+            }
+            backoff(RETRY_BACKOFF * 2u32.pow(attempt - 1), cancel);
+        }
+    }
+
+    /// Drop workers whose process has exited and report what is left
+    pub fn health_check(&self) -> Vec<WorkerStatus> {
+        let mut idle = self.idle.lock().unwrap();
+        idle.retain(|_, workers| {
+            workers.retain_mut(|worker| worker.is_alive());
+            !workers.is_empty()
+        });
+        idle.iter()
+            .map(|(key, workers)| WorkerStatus {
+                model: key.model.clone(),
+                idle: workers.len(),
+            })
+            .collect()
+    }
+
+    /// Stop every idle worker
+    pub fn shutdown(&self) {
+        self.idle.lock().unwrap().clear();
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    /// A stand-in for Piper that answers two requests and then exits
+    fn fake_piper(dir: &Path) -> PathBuf {
+        let script = dir.join("piper");
+        std::fs::write(
+            &script,
+            "#!/bin/sh\n\
+             n=0\n\
+             while IFS= read -r line; do\n\
+               out=$(printf '%s' \"$line\" | sed 's/.*\"output_file\":\"\\([^\"]*\\)\".*/\\1/')\n\
+               printf 'RIFF' > \"$out\"\n\
+               echo \"$out\"\n\
+               n=$((n+1))\n\
+               [ \"$n\" -ge 2 ] && exit 0\n\
+             done\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        script
+    }
+
+    /// Process ids of the idle workers for `key`
+    fn idle_pids(pool: &WorkerPool, key: &WorkerKey) -> Vec<u32> {
+        pool.idle.lock().unwrap()[key]
+            .iter()
+            .map(|worker| worker.child.lock().unwrap().id())
+            .collect()
+    }
+
+    #[test]
+    fn test_worker_is_reused_and_restarted() {
+        let dir = std::env::temp_dir().join(format!("piper_worker_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let key = WorkerKey {
+            program: fake_piper(&dir),
+            model: "voice.onnx".to_string(),
+            args: Vec::new(),
+        };
+        let pool = WorkerPool::new();
+
+        let mut pids = Vec::new();
+        for i in 0..4 {
+            let output = dir.join(format!("out_{}.wav", i));
+            pool.synthesize(&key, "Hello\nthere", &output, Some(1), &CancelToken::new())
+                .unwrap();
+            assert!(output.exists());
+            pids.push(idle_pids(&pool, &key));
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // One process serves two requests, then exits and is replaced
+        assert_eq!(pids[0].len(), 1);
+        assert_eq!(pids[0], pids[1]);
+        assert_ne!(pids[1], pids[2]);
+        assert_eq!(pids[2], pids[3]);
+    }
+
+    #[test]
+    fn test_cancel_kills_busy_worker() {
+        let dir = std::env::temp_dir().join(format!("piper_cancel_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("piper");
+        // `exec` so killing the process also closes its stdout
+        std::fs::write(&script, "#!/bin/sh\nread -r line\nexec sleep 30\n").unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let key = WorkerKey {
+            program: script,
+            model: "voice.onnx".to_string(),
+            args: Vec::new(),
+        };
+
+        let cancel = CancelToken::new();
+        let canceller = cancel.clone();
+        let started = std::time::Instant::now();
+        thread::spawn(move || {
+            thread::sleep(std::time::Duration::from_millis(200));
+            canceller.cancel();
+        });
+        let result = WorkerPool::new().synthesize(&key, "Hello", &dir.join("out.wav"), None, &cancel);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(result.is_err());
+        assert!(started.elapsed() < std::time::Duration::from_secs(10));
+    }
+}
@@ -0,0 +1,130 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::audio::DEFAULT_TICK_MS;
+use crate::cache::DEFAULT_CACHE_MAX_MB;
+use crate::dialogue::DialogueVoices;
+use crate::loudness::LoudnessSettings;
+use crate::pauses::PausePolicy;
+use crate::tts_engine::{BackendKind, VoiceProfile};
+
+/// Environment variable overriding the configured Piper executable
+pub const PIPER_PATH_ENV: &str = "PIPER_PATH";
+/// Environment variable overriding the configured voices directory
+pub const PIPER_VOICES_DIR_ENV: &str = "PIPER_VOICES_DIR";
+/// Environment variable overriding the TTS engine, e.g. `mock` in CI
+pub const TTS_BACKEND_ENV: &str = "TTS_BACKEND";
+
+const SETTINGS_FILE: &str = "settings.json";
+
+/// User settings persisted as JSON in the app config directory
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AppSettings {
+    /// Path to the Piper executable
+    pub piper_path: Option<String>,
+    /// Directory containing Piper `.onnx` voice models
+    pub voices_dir: Option<String>,
+    /// Speech engine used when a document doesn't pick one
+    pub backend: BackendKind,
+    /// Default voice and synthesis parameters for new audio
+    pub voice: VoiceProfile,
+    /// Maximum number of engine processes run at once; defaults to the CPU count
+    pub synthesis_concurrency: Option<usize>,
+    /// Size limit for cached synthesized audio, in megabytes
+    pub cache_max_mb: Option<u64>,
+    /// Silence between sentences, paragraphs, headings and chapters
+    pub pauses: PausePolicy,
+    /// Separate narrator and dialogue voices for fiction
+    pub dialogue: DialogueVoices,
+    /// Interval between playback position events, in milliseconds
+    pub position_tick_ms: Option<u64>,
+    /// Name of the audio output to play through; the system default if unset
+    pub output_device: Option<String>,
+    /// Loudness normalization and compression during playback
+    pub loudness: LoudnessSettings,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SettingsError {
+    pub message: String,
+}
+
+impl AppSettings {
+    /// Piper executable, with the environment variable taking precedence
+    pub fn effective_piper_path(&self) -> Option<PathBuf> {
+        env_path(PIPER_PATH_ENV).or_else(|| non_empty_path(self.piper_path.as_deref()))
+    }
+
+    /// Voices directory, with the environment variable taking precedence
+    pub fn effective_voices_dir(&self) -> Option<PathBuf> {
+        env_path(PIPER_VOICES_DIR_ENV).or_else(|| non_empty_path(self.voices_dir.as_deref()))
+    }
+
+    /// Size limit for the synthesis cache in bytes
+    pub fn cache_max_bytes(&self) -> u64 {
+        self.cache_max_mb
+            .unwrap_or(DEFAULT_CACHE_MAX_MB)
+            .saturating_mul(1024 * 1024)
+    }
+
+    /// Interval between playback position events
+    pub fn position_tick_ms(&self) -> u64 {
+        self.position_tick_ms.unwrap_or(DEFAULT_TICK_MS)
+    }
+
+    /// TTS engine, with the environment variable taking precedence
+    pub fn effective_backend(&self) -> BackendKind {
+        std::env::var(TTS_BACKEND_ENV)
+            .ok()
+            .and_then(|value| BackendKind::parse(&value))
+            .unwrap_or(self.backend)
+    }
+}
+
+fn env_path(name: &str) -> Option<PathBuf> {
+    std::env::var(name)
+        .ok()
+        .and_then(|value| non_empty_path(Some(&value)))
+}
+
+fn non_empty_path(value: Option<&str>) -> Option<PathBuf> {
+    value
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Location of the settings file inside the given config directory
+pub fn settings_file(config_dir: &Path) -> PathBuf {
+    config_dir.join(SETTINGS_FILE)
+}
+
+/// Load settings, with defaults if the file is missing. An invalid file is
+/// an error, so the caller can warn before falling back to defaults.
+pub fn load_settings(path: &Path) -> Result<AppSettings, SettingsError> {
+    match fs::read_to_string(path) {
+        Ok(contents) => serde_json::from_str(&contents).map_err(|e| SettingsError {
+            message: format!("Invalid settings file {}: {}", path.display(), e),
+        }),
+        Err(_) => Ok(AppSettings::default()),
+    }
+}
+
+/// Write settings to disk, creating the parent directory if needed
+pub fn save_settings(path: &Path, settings: &AppSettings) -> Result<(), SettingsError> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|e| SettingsError {
+            message: format!("Failed to create settings dir: {}", e),
+        })?;
+    }
+
+    let contents = serde_json::to_string_pretty(settings).map_err(|e| SettingsError {
+        message: format!("Failed to serialize settings: {}", e),
+    })?;
+
+    fs::write(path, contents).map_err(|e| SettingsError {
+        message: format!("Failed to write settings: {}", e),
+    })
+}
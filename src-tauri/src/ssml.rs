@@ -0,0 +1,904 @@
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::jobs::JobId;
+use crate::pauses::{block_kind, BlockKind, PausePolicy};
+use crate::synthesis::TextChunk;
+use crate::tts_engine::{
+    BackendKind, RawAudio, TtsBackend, TtsError, TtsErrorKind, TtsResult, VoiceInfo,
+    VoiceProfile, WordTiming,
+};
+use crate::wav::{frames_to_ms, ms_to_samples, resample, write_wav};
+
+/// Sample rate used for a script that is nothing but breaks
+const FALLBACK_SAMPLE_RATE: u32 = 22050;
+/// Longest break honoured, so a typo can't insert minutes of silence
+const MAX_BREAK_MS: u64 = 10_000;
+/// Silence kept at each end of a segment when joining segments
+const EDGE_MARGIN_MS: u64 = 30;
+/// Pause where segments meet between two words of the same clause
+const WORD_JOIN_MS: u64 = 60;
+/// Pause where segments meet after a comma, semicolon or colon
+const CLAUSE_JOIN_MS: u64 = 200;
+/// Pause after a sentence when the profile doesn't set one
+const DEFAULT_SENTENCE_MS: u64 = 300;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SsmlError {
+    pub message: String,
+}
+
+fn ssml_error(message: impl Into<String>) -> SsmlError {
+    SsmlError {
+        message: message.into(),
+    }
+}
+
+/// Parsed markup: elements keep their attributes, text has entities decoded
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Text(String),
+    Element {
+        name: String,
+        attrs: Vec<(String, String)>,
+        children: Vec<Node>,
+    },
+}
+
+/// A word as shown to the reader and the tokens the engine is given for it.
+/// `say-as` and `phoneme` make the two differ.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpokenUnit {
+    pub display: Vec<String>,
+    pub spoken: Vec<String>,
+}
+
+impl SpokenUnit {
+    /// A word spoken as written
+    pub fn word(word: &str) -> Self {
+        SpokenUnit {
+            display: vec![word.to_string()],
+            spoken: vec![word.to_string()],
+        }
+    }
+}
+
+/// A stretch of the script synthesized in one request, or a silence
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Speech {
+        units: Vec<SpokenUnit>,
+        /// Multiplies the profile's length scale
+        length_factor: f32,
+    },
+    Silence(u64),
+}
+
+/// Whether `text` is an SSML document rather than plain text
+pub fn is_ssml(text: &str) -> bool {
+    text.trim_start().starts_with("<speak")
+}
+
+fn decode_entities(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+fn encode_entities(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Split a tag's contents into its name and attributes
+fn parse_tag(tag: &str) -> Result<(String, Vec<(String, String)>), SsmlError> {
+    let tag = tag.trim();
+    let name_end = tag.find(char::is_whitespace).unwrap_or(tag.len());
+    let name = tag[..name_end].to_string();
+    if name.is_empty() {
+        return Err(ssml_error("Empty tag"));
+    }
+
+    let mut attrs = Vec::new();
+    let mut rest = tag[name_end..].trim_start();
+    while !rest.is_empty() {
+        let eq = rest
+            .find('=')
+            .ok_or_else(|| ssml_error(format!("Attribute without a value in <{}>", name)))?;
+        let key = rest[..eq].trim().to_string();
+        let value_start = rest[eq + 1..].trim_start();
+        let quote = value_start
+            .chars()
+            .next()
+            .filter(|c| *c == '"' || *c == '\'')
+            .ok_or_else(|| ssml_error(format!("Unquoted attribute {} in <{}>", key, name)))?;
+        let value_end = value_start[1..]
+            .find(quote)
+            .ok_or_else(|| ssml_error(format!("Unterminated attribute {} in <{}>", key, name)))?;
+        attrs.push((key, decode_entities(&value_start[1..1 + value_end])));
+        rest = value_start[value_end + 2..].trim_start();
+    }
+
+    Ok((name, attrs))
+}
+
+/// Parse markup into a tree. Comments, processing instructions and
+/// declarations are skipped.
+fn parse_nodes(input: &str) -> Result<Vec<Node>, SsmlError> {
+    type Open = (String, Vec<(String, String)>, Vec<Node>);
+    let mut stack: Vec<Open> = vec![(String::new(), Vec::new(), Vec::new())];
+    let mut rest = input;
+
+    while !rest.is_empty() {
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            let end = comment
+                .find("-->")
+                .ok_or_else(|| ssml_error("Unterminated comment"))?;
+            rest = &comment[end + 3..];
+        } else if let Some(after) = rest.strip_prefix('<') {
+            let end = after.find('>').ok_or_else(|| ssml_error("Unterminated tag"))?;
+            let tag = &after[..end];
+            rest = &after[end + 1..];
+
+            if tag.starts_with(['?', '!']) {
+                continue;
+            }
+            if let Some(closing) = tag.strip_prefix('/') {
+                let closing = closing.trim();
+                let (name, attrs, children) = stack.pop().unwrap();
+                if stack.is_empty() || name != closing {
+                    return Err(ssml_error(format!("Unexpected </{}>", closing)));
+                }
+                stack.last_mut().unwrap().2.push(Node::Element {
+                    name,
+                    attrs,
+                    children,
+                });
+            } else if let Some(tag) = tag.strip_suffix('/') {
+                let (name, attrs) = parse_tag(tag)?;
+                stack.last_mut().unwrap().2.push(Node::Element {
+                    name,
+                    attrs,
+                    children: Vec::new(),
+                });
+            } else {
+                let (name, attrs) = parse_tag(tag)?;
+                stack.push((name, attrs, Vec::new()));
+            }
+        } else {
+            let end = rest.find('<').unwrap_or(rest.len());
+            let text = decode_entities(&rest[..end]);
+            stack.last_mut().unwrap().2.push(Node::Text(text));
+            rest = &rest[end..];
+        }
+    }
+
+    if stack.len() > 1 {
+        return Err(ssml_error(format!("Unclosed <{}>", stack.last().unwrap().0)));
+    }
+    Ok(stack.pop().unwrap().2)
+}
+
+fn serialize_nodes(nodes: &[Node], out: &mut String) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(&encode_entities(text)),
+            Node::Element {
+                name,
+                attrs,
+                children,
+            } => {
+                out.push('<');
+                out.push_str(name);
+                for (key, value) in attrs {
+                    out.push_str(&format!(" {}=\"{}\"", key, encode_entities(value)));
+                }
+                if children.is_empty() {
+                    out.push_str("/>");
+                } else {
+                    out.push('>');
+                    serialize_nodes(children, out);
+                    out.push_str(&format!("</{}>", name));
+                }
+            }
+        }
+    }
+}
+
+fn attr<'a>(attrs: &'a [(String, String)], key: &str) -> Option<&'a str> {
+    attrs
+        .iter()
+        .find(|(k, _)| k == key)
+        .map(|(_, v)| v.as_str())
+}
+
+fn text_content(nodes: &[Node]) -> String {
+    let mut text = String::new();
+    for node in nodes {
+        match node {
+            Node::Text(t) => text.push_str(t),
+            Node::Element { children, .. } => {
+                text.push(' ');
+                text.push_str(&text_content(children));
+                text.push(' ');
+            }
+        }
+    }
+    text
+}
+
+/// Length of a `<break>`, from its `time` or `strength`
+fn break_ms(attrs: &[(String, String)]) -> Result<u64, SsmlError> {
+    let ms = if let Some(time) = attr(attrs, "time") {
+        let time = time.trim();
+        let parsed = if let Some(ms) = time.strip_suffix("ms") {
+            ms.trim().parse::<f64>().ok()
+        } else if let Some(s) = time.strip_suffix('s') {
+            s.trim().parse::<f64>().ok().map(|s| s * 1000.0)
+        } else {
+            None
+        };
+        match parsed {
+            Some(ms) if ms >= 0.0 => ms.round() as u64,
+            _ => return Err(ssml_error(format!("Invalid break time \"{}\"", time))),
+        }
+    } else {
+        match attr(attrs, "strength").unwrap_or("medium") {
+            "none" => 0,
+            "x-weak" => 100,
+            "weak" => 250,
+            "medium" => 500,
+            "strong" => 1000,
+            "x-strong" => 2000,
+            other => return Err(ssml_error(format!("Invalid break strength \"{}\"", other))),
+        }
+    };
+    Ok(ms.min(MAX_BREAK_MS))
+}
+
+/// Piper has no emphasis control, so emphasis is rendered as slower speech
+fn emphasis_factor(level: Option<&str>) -> Result<f32, SsmlError> {
+    match level.unwrap_or("moderate") {
+        "strong" => Ok(1.25),
+        "moderate" => Ok(1.1),
+        "none" => Ok(1.0),
+        "reduced" => Ok(0.9),
+        other => Err(ssml_error(format!("Invalid emphasis level \"{}\"", other))),
+    }
+}
+
+/// Tokens to speak for `<say-as>`. Numbers, dates and other formats are
+/// left to the engine's own text normalization.
+fn say_as(interpret_as: &str, text: &str) -> Vec<String> {
+    match interpret_as {
+        "characters" | "spell-out" | "verbatim" => text
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .map(|c| c.to_string())
+            .collect(),
+        "digits" | "telephone" => text
+            .chars()
+            .filter(|c| c.is_alphanumeric())
+            .map(|c| c.to_string())
+            .collect(),
+        _ => text.split_whitespace().map(str::to_string).collect(),
+    }
+}
+
+/// IPA symbols and the eSpeak phoneme mnemonics eSpeak NG reads inside
+/// `[[ ]]`. Diphthongs and long vowels come first so they win over their
+/// first symbol.
+const IPA_TO_ESPEAK: [(&str, &str); 56] = [
+    ("eɪ", "eI"), ("aɪ", "aI"), ("ɔɪ", "OI"), ("aʊ", "aU"), ("oʊ", "oU"), ("əʊ", "@U"),
+    ("ɪə", "i@"), ("eə", "e@"), ("ʊə", "U@"), ("tʃ", "tS"), ("dʒ", "dZ"),
+    ("ɜː", "3:"), ("ɑː", "A:"), ("ɔː", "O:"), ("uː", "u:"), ("iː", "i:"),
+    ("ə", "@"), ("ɚ", "3"), ("ɜ", "3:"), ("æ", "a"), ("ɑ", "A:"), ("ɒ", "0"), ("ɔ", "O"),
+    ("ʊ", "U"), ("u", "u:"), ("ɪ", "I"), ("i", "i:"), ("e", "e"), ("ɛ", "E"), ("ʌ", "V"),
+    ("θ", "T"), ("ð", "D"), ("ʃ", "S"), ("ʒ", "Z"), ("ŋ", "N"), ("ɹ", "r"), ("ɡ", "g"),
+    ("ʔ", "?"), ("p", "p"), ("b", "b"), ("t", "t"), ("d", "d"), ("k", "k"), ("g", "g"),
+    ("f", "f"), ("v", "v"), ("s", "s"), ("z", "z"), ("h", "h"), ("m", "m"), ("n", "n"),
+    ("l", "l"), ("r", "r"), ("j", "j"), ("w", "w"), ("x", "x"),
+];
+
+/// Translate an IPA transcription into eSpeak mnemonics. `None` if it uses
+/// a symbol eSpeak's English phonemes don't cover.
+fn ipa_to_espeak(ipa: &str) -> Option<String> {
+    let mut out = String::new();
+    let mut rest = ipa.trim();
+    while let Some(c) = rest.chars().next() {
+        let (symbol, mnemonic) = match c {
+            'ˈ' => ("ˈ", "'"),
+            'ˌ' => ("ˌ", ","),
+            // Length is part of the vowels above; syllable breaks aren't marked
+            'ː' | '.' => (&rest[..c.len_utf8()], ""),
+            _ if c.is_whitespace() => (&rest[..c.len_utf8()], " "),
+            _ => IPA_TO_ESPEAK.iter().copied().find(|(symbol, _)| rest.starts_with(symbol))?,
+        };
+        out.push_str(mnemonic);
+        rest = &rest[symbol.len()..];
+    }
+    Some(out)
+}
+
+/// Flattened script content, before grouping into segments
+enum Piece {
+    Unit(SpokenUnit, f32),
+    Break(u64),
+}
+
+/// Walk the tree, applying markup to the words beneath it. Unsupported
+/// elements are read as their text.
+fn collect_pieces(
+    nodes: &[Node],
+    length_factor: f32,
+    phoneme_input: bool,
+    out: &mut Vec<Piece>,
+) -> Result<(), SsmlError> {
+    for node in nodes {
+        let (name, attrs, children) = match node {
+            Node::Text(text) => {
+                for word in text.split_whitespace() {
+                    out.push(Piece::Unit(SpokenUnit::word(word), length_factor));
+                }
+                continue;
+            }
+            Node::Element {
+                name,
+                attrs,
+                children,
+            } => (name.as_str(), attrs, children),
+        };
+
+        match name {
+            "break" => out.push(Piece::Break(break_ms(attrs)?)),
+            "emphasis" => {
+                let factor = length_factor * emphasis_factor(attr(attrs, "level"))?;
+                collect_pieces(children, factor, phoneme_input, out)?;
+            }
+            "say-as" | "phoneme" => {
+                let text = text_content(children);
+                let display: Vec<String> = text.split_whitespace().map(str::to_string).collect();
+                // eSpeak NG reads phoneme mnemonics inside [[ ]]. Each word
+                // is bracketed on its own so it stays one token for word
+                // timings. Other alphabets, and IPA it can't translate, are
+                // read as text.
+                let phonemes = attr(attrs, "ph")
+                    .filter(|_| phoneme_input && attr(attrs, "alphabet").unwrap_or("ipa") == "ipa")
+                    .and_then(ipa_to_espeak);
+                let spoken = match (name, phonemes) {
+                    ("say-as", _) => say_as(attr(attrs, "interpret-as").unwrap_or(""), &text),
+                    (_, Some(phonemes)) => phonemes
+                        .split_whitespace()
+                        .map(|word| format!("[[{}]]", word))
+                        .collect(),
+                    _ => display.clone(),
+                };
+                if !display.is_empty() || !spoken.is_empty() {
+                    out.push(Piece::Unit(SpokenUnit { display, spoken }, length_factor));
+                }
+            }
+            _ => collect_pieces(children, length_factor, phoneme_input, out)?,
+        }
+    }
+    Ok(())
+}
+
+/// Translate markup into speech segments and silences. Phonemes are passed
+/// through only when `phoneme_input` is set; otherwise their text is read.
+fn segments(input: &str, phoneme_input: bool) -> Result<Vec<Segment>, SsmlError> {
+    let mut pieces = Vec::new();
+    collect_pieces(&parse_nodes(input)?, 1.0, phoneme_input, &mut pieces)?;
+
+    let mut segments: Vec<Segment> = Vec::new();
+    for piece in pieces {
+        match piece {
+            Piece::Break(ms) => match segments.last_mut() {
+                Some(Segment::Silence(previous)) => *previous += ms,
+                _ => segments.push(Segment::Silence(ms)),
+            },
+            Piece::Unit(unit, factor) => match segments.last_mut() {
+                Some(Segment::Speech {
+                    units,
+                    length_factor,
+                }) if *length_factor == factor => units.push(unit),
+                _ => segments.push(Segment::Speech {
+                    units: vec![unit],
+                    length_factor: factor,
+                }),
+            },
+        }
+    }
+    Ok(segments)
+}
+
+/// The words a reader sees in an SSML document, without markup
+pub fn display_words(input: &str) -> Result<Vec<String>, SsmlError> {
+    let words = segments(input, false)?
+        .into_iter()
+        .flat_map(|segment| match segment {
+            Segment::Speech { units, .. } => units,
+            Segment::Silence(_) => Vec::new(),
+        })
+        .flat_map(|unit| unit.display)
+        .collect();
+    Ok(words)
+}
+
+/// An SSML document split into paragraphs for synthesis
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SsmlScript {
+    /// Paragraph text as shown to the reader
+    pub paragraphs: Vec<String>,
+    /// One chunk per paragraph, each a standalone `<speak>` document
+    pub chunks: Vec<TextChunk>,
+}
+
+/// A synthesis job started from an SSML script
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SsmlJob {
+    pub job_id: JobId,
+    /// Paragraph text as shown to the reader
+    pub paragraphs: Vec<String>,
+}
+
+/// Split a document into one chunk per `<p>`; content outside paragraphs
+/// forms paragraphs of its own. Pauses between paragraphs follow `policy`.
+pub fn plan_ssml(input: &str, policy: &PausePolicy) -> Result<SsmlScript, SsmlError> {
+    let nodes = parse_nodes(input)?;
+    let body = match nodes.iter().find(|n| matches!(n, Node::Element { name, .. } if name == "speak")) {
+        Some(Node::Element { children, .. }) => children.clone(),
+        _ => return Err(ssml_error("Expected a <speak> document")),
+    };
+
+    let mut groups: Vec<Vec<Node>> = vec![Vec::new()];
+    for node in body {
+        if matches!(&node, Node::Element { name, .. } if name == "p") {
+            groups.push(vec![node]);
+            groups.push(Vec::new());
+        } else {
+            groups.last_mut().unwrap().push(node);
+        }
+    }
+
+    let mut script = SsmlScript {
+        paragraphs: Vec::new(),
+        chunks: Vec::new(),
+    };
+    let mut previous_kind: Option<BlockKind> = None;
+    let mut word_index = 0;
+    for group in groups {
+        let mut text = String::from("<speak>");
+        serialize_nodes(&group, &mut text);
+        text.push_str("</speak>");
+
+        let words = display_words(&text)?;
+        if words.is_empty() {
+            continue;
+        }
+        let paragraph = words.join(" ");
+        let kind = block_kind(&paragraph);
+        script.chunks.push(TextChunk {
+            index: script.chunks.len(),
+            paragraph: script.paragraphs.len(),
+            first_word: word_index,
+            text,
+            pause_ms: previous_kind.map_or(0, |previous| policy.between(previous, kind)),
+            quote_open: false,
+            word_count: words.len(),
+        });
+        script.paragraphs.push(paragraph);
+        previous_kind = Some(kind);
+        word_index += words.len();
+    }
+
+    Ok(script)
+}
+
+/// Timings for display words from the timings of the tokens spoken for them.
+/// Words sharing a unit split its span evenly.
+fn unit_timings(units: &[SpokenUnit], spoken: &[WordTiming], offset_ms: u64) -> Vec<WordTiming> {
+    let mut timings = Vec::new();
+    let mut cursor = 0;
+    let mut previous_end = offset_ms;
+
+    for unit in units {
+        let span = &spoken[cursor.min(spoken.len())..(cursor + unit.spoken.len()).min(spoken.len())];
+        cursor += unit.spoken.len();
+        let (start, end) = match (span.first(), span.last()) {
+            (Some(first), Some(last)) => (offset_ms + first.start_ms, offset_ms + last.end_ms),
+            _ => (previous_end, previous_end),
+        };
+
+        let count = unit.display.len() as u64;
+        for (i, word) in unit.display.iter().enumerate() {
+            let i = i as u64;
+            timings.push(WordTiming {
+                word: word.clone(),
+                start_ms: start + (end - start) * i / count,
+                end_ms: start + (end - start) * (i + 1) / count,
+            });
+        }
+        previous_end = end;
+    }
+
+    timings
+}
+
+/// Speech synthesized in one request with its own voice settings, or an
+/// explicit silence
+#[derive(Debug, Clone, PartialEq)]
+pub enum VoiceSegment {
+    Speech {
+        units: Vec<SpokenUnit>,
+        profile: VoiceProfile,
+    },
+    Silence(u64),
+}
+
+/// Pause the engine would have made after the segment's last word, had the
+/// text not been split there
+fn join_gap_ms(units: &[SpokenUnit], profile: &VoiceProfile) -> u64 {
+    let last = units.iter().rev().find_map(|unit| unit.spoken.last());
+    match last.map(|word| word.trim_end_matches(['"', '\'', ')', ']', '\u{201d}', '\u{2019}', '\u{bb}'])) {
+        Some(word) if word.ends_with(['.', '!', '?']) => profile
+            .sentence_silence
+            .map(|s| (s as f64 * 1000.0).round() as u64)
+            .unwrap_or(DEFAULT_SENTENCE_MS),
+        Some(word) if word.ends_with([',', ';', ':']) => CLAUSE_JOIN_MS,
+        _ => WORD_JOIN_MS,
+    }
+}
+
+/// Samples left after trimming silence at either end down to a margin
+fn trim_range(samples: &[i16], sample_rate: u32) -> Range<usize> {
+    let peak = samples.iter().map(|s| s.unsigned_abs()).max().unwrap_or(0);
+    let threshold = (peak / 50).max(1);
+    let (Some(first), Some(last)) = (
+        samples.iter().position(|s| s.unsigned_abs() >= threshold),
+        samples.iter().rposition(|s| s.unsigned_abs() >= threshold),
+    ) else {
+        return 0..samples.len();
+    };
+    let margin = ms_to_samples(EDGE_MARGIN_MS, sample_rate);
+    first.saturating_sub(margin)..(last + 1 + margin).min(samples.len())
+}
+
+/// Synthesize each segment through `backend` and join them into one mono
+/// recording in memory. Edge silence the engine adds between segments is
+/// replaced by the pause the text calls for, so segments meet seamlessly,
+/// and word timings are reported for the display words. The recording's
+/// outer edges are left as the engine made them, like any other chunk's.
+/// Every segment is resampled to `sample_rate`, or to the first segment's
+/// rate if that isn't known.
+pub fn render_segments_raw(
+    backend: &dyn TtsBackend,
+    segments: Vec<VoiceSegment>,
+    sample_rate: Option<u32>,
+) -> Result<RawAudio, TtsError> {
+    let mut samples: Vec<i16> = Vec::new();
+    let mut sample_rate = sample_rate;
+    let mut explicit_silence_ms: Option<u64> = None;
+    let mut join_ms = 0;
+    let mut word_timings = Vec::new();
+    let last_speech = segments
+        .iter()
+        .rposition(|segment| matches!(segment, VoiceSegment::Speech { .. }));
+
+    for (index, segment) in segments.into_iter().enumerate() {
+        let (units, profile) = match segment {
+            VoiceSegment::Silence(ms) => {
+                *explicit_silence_ms.get_or_insert(0) += ms;
+                continue;
+            }
+            VoiceSegment::Speech { units, profile } => (units, profile),
+        };
+
+        let spoken: Vec<&str> = units
+            .iter()
+            .flat_map(|unit| unit.spoken.iter().map(String::as_str))
+            .collect();
+        let audio = backend.synthesize_raw(&spoken.join(" "), &profile)?;
+        if audio.channels != 1 {
+            return Err(TtsError::new(TtsErrorKind::Other, "Expected mono audio from the engine"));
+        }
+        let rate = *sample_rate.get_or_insert(audio.sample_rate);
+        let audio_samples = resample(&audio.samples, audio.sample_rate, rate);
+        let mut kept = trim_range(&audio_samples, rate);
+        if samples.is_empty() && explicit_silence_ms.is_none() {
+            kept.start = 0;
+        }
+        if Some(index) == last_speech {
+            kept.end = audio_samples.len();
+        }
+        let lead_ms = frames_to_ms(kept.start as u64, rate);
+        let kept_ms = frames_to_ms(kept.len() as u64, rate);
+
+        let gap_ms = explicit_silence_ms.take().unwrap_or(join_ms);
+        samples.extend(std::iter::repeat_n(0, ms_to_samples(gap_ms, rate)));
+        let offset_ms = frames_to_ms(samples.len() as u64, rate);
+        let spoken_timings: Vec<WordTiming> = audio
+            .word_timings
+            .iter()
+            .map(|t| WordTiming {
+                word: t.word.clone(),
+                start_ms: t.start_ms.saturating_sub(lead_ms).min(kept_ms),
+                end_ms: t.end_ms.saturating_sub(lead_ms).min(kept_ms),
+            })
+            .collect();
+        word_timings.extend(unit_timings(&units, &spoken_timings, offset_ms));
+        samples.extend_from_slice(&audio_samples[kept]);
+        join_ms = join_gap_ms(&units, &profile);
+    }
+
+    let rate = sample_rate.unwrap_or(FALLBACK_SAMPLE_RATE);
+    // The pause after the chunk is left to the gap before the next one
+    let trailing_ms = explicit_silence_ms.unwrap_or(0);
+    samples.extend(std::iter::repeat_n(0, ms_to_samples(trailing_ms, rate)));
+
+    Ok(RawAudio {
+        sample_rate: rate,
+        channels: 1,
+        samples,
+        word_timings,
+    })
+}
+
+/// Render segments as `render_segments_raw` does into a WAV at `output_path`
+pub fn render_segments(
+    backend: &dyn TtsBackend,
+    segments: Vec<VoiceSegment>,
+    output_path: &str,
+    sample_rate: Option<u32>,
+) -> Result<TtsResult, TtsError> {
+    let audio = render_segments_raw(backend, segments, sample_rate)?;
+    write_wav(Path::new(output_path), audio.sample_rate, &audio.samples)
+        .map_err(|e| TtsError::new(TtsErrorKind::Io, e.message))?;
+
+    Ok(TtsResult {
+        audio_path: output_path.to_string(),
+        duration_ms: frames_to_ms(audio.samples.len() as u64, audio.sample_rate),
+        word_timings: audio.word_timings,
+    })
+}
+
+/// Accepts SSML documents in place of plain text, synthesizing each segment
+/// through the wrapped backend and joining them with the requested silences
+pub struct SsmlBackend {
+    inner: Arc<dyn TtsBackend>,
+}
+
+impl SsmlBackend {
+    pub fn new(inner: Arc<dyn TtsBackend>) -> Self {
+        SsmlBackend { inner }
+    }
+
+    /// The segments an SSML document is spoken as, each with its profile
+    fn voice_segments(&self, text: &str, profile: &VoiceProfile) -> Result<Vec<VoiceSegment>, TtsError> {
+        // eSpeak NG documents phoneme input in [[ ]]; Piper's phonemizer
+        // isn't known to honour it, so Piper reads the element's text
+        let phoneme_input = self.inner.kind() == BackendKind::Espeak;
+        Ok(segments(text, phoneme_input)
+            .map_err(|e| TtsError::new(TtsErrorKind::InvalidMarkup, e.message))?
+            .into_iter()
+            .map(|segment| match segment {
+                Segment::Silence(ms) => VoiceSegment::Silence(ms),
+                Segment::Speech {
+                    units,
+                    length_factor,
+                } => VoiceSegment::Speech {
+                    units,
+                    profile: VoiceProfile {
+                        length_scale: if length_factor == 1.0 {
+                            profile.length_scale
+                        } else {
+                            Some((profile.length_scale.unwrap_or(1.0) * length_factor).clamp(0.1, 5.0))
+                        },
+                        ..profile.clone()
+                    },
+                },
+            })
+            .collect())
+    }
+}
+
+impl TtsBackend for SsmlBackend {
+    fn kind(&self) -> BackendKind {
+        self.inner.kind()
+    }
+
+    fn is_available(&self) -> bool {
+        self.inner.is_available()
+    }
+
+    fn list_voices(&self) -> Vec<VoiceInfo> {
+        self.inner.list_voices()
+    }
+
+    fn cancel(&self) {
+        self.inner.cancel();
+    }
+
+    fn sample_rate(&self, profile: &VoiceProfile) -> Option<u32> {
+        self.inner.sample_rate(profile)
+    }
+
+    fn synthesize(
+        &self,
+        text: &str,
+        output_path: &str,
+        profile: &VoiceProfile,
+    ) -> Result<TtsResult, TtsError> {
+        if is_ssml(text) {
+            let segments = self.voice_segments(text, profile)?;
+            render_segments(self.inner.as_ref(), segments, output_path, self.sample_rate(profile))
+        } else {
+            self.inner.synthesize(text, output_path, profile)
+        }
+    }
+
+    fn synthesize_raw(&self, text: &str, profile: &VoiceProfile) -> Result<RawAudio, TtsError> {
+        if is_ssml(text) {
+            let segments = self.voice_segments(text, profile)?;
+            render_segments_raw(self.inner.as_ref(), segments, self.sample_rate(profile))
+        } else {
+            self.inner.synthesize_raw(text, profile)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_engine::MockBackend;
+
+    const SCRIPT: &str = r#"<?xml version="1.0"?>
+<speak>
+  <p>Call <say-as interpret-as="characters">NASA</say-as> now.<break time="1.5s"/></p>
+  <p>It is <emphasis level="strong">very</emphasis> <phoneme alphabet="ipa" ph="təˈmɑːtoʊ">tomato</phoneme> &amp; more.</p>
+</speak>"#;
+
+    #[test]
+    fn test_segments_translate_markup() {
+        let segments = segments(SCRIPT, true).unwrap();
+        let spoken: Vec<String> = segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Speech { units, length_factor } => format!(
+                    "{}@{}",
+                    units.iter().flat_map(|u| u.spoken.clone()).collect::<Vec<_>>().join(" "),
+                    length_factor
+                ),
+                Segment::Silence(ms) => format!("{}ms", ms),
+            })
+            .collect();
+        assert_eq!(
+            spoken,
+            vec![
+                "Call N A S A now.@1",
+                "1500ms",
+                "It is@1",
+                "very@1.25",
+                "[[t@'mA:toU]] & more.@1",
+            ]
+        );
+
+        let script = plan_ssml(SCRIPT, &PausePolicy::default()).unwrap();
+        assert_eq!(script.paragraphs, vec!["Call NASA now.", "It is very tomato & more."]);
+        assert_eq!(script.chunks[1].first_word, 3);
+        assert_eq!(script.chunks[1].word_count, 6);
+
+        assert_eq!(ipa_to_espeak("ˈθɪŋk ðɛə").as_deref(), Some("'TINk DE@"));
+        // Clicks aren't English phonemes, and other alphabets aren't
+        // translated, so the word is read instead
+        assert_eq!(ipa_to_espeak("ǃa"), None);
+        let sampa = super::segments(r#"<speak><phoneme alphabet="x-sampa" ph="t@">tomato</phoneme></speak>"#, true).unwrap();
+        assert!(matches!(&sampa[..], [Segment::Speech { units, .. }] if units[0].spoken == ["tomato"]));
+    }
+
+    #[test]
+    fn test_timings_follow_display_words() {
+        let path = std::env::temp_dir().join(format!("ssml_{}.wav", std::process::id()));
+        let backend = SsmlBackend::new(Arc::new(MockBackend::new()));
+        let result = backend
+            .synthesize(
+                r#"<speak>Say <say-as interpret-as="characters">ABC</say-as><break time="500ms"/> twice.</speak>"#,
+                &path.to_string_lossy(),
+                &VoiceProfile::default(),
+            )
+            .unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        let words: Vec<&str> = result.word_timings.iter().map(|t| t.word.as_str()).collect();
+        assert_eq!(words, vec!["Say", "ABC", "twice."]);
+        // The break falls between "ABC" and "twice."
+        let gap = result.word_timings[2].start_ms - result.word_timings[1].end_ms;
+        assert!(gap >= 500, "gap was {}ms", gap);
+        assert!(result.duration_ms >= result.word_timings[2].end_ms);
+    }
+
+    /// Reports itself as eSpeak, records what it was asked to say and
+    /// speaks it with the mock.
+    #[derive(Default)]
+    struct PhonemeBackend {
+        said: std::sync::Mutex<Vec<String>>,
+    }
+
+    impl TtsBackend for PhonemeBackend {
+        fn kind(&self) -> BackendKind {
+            BackendKind::Espeak
+        }
+
+        fn is_available(&self) -> bool {
+            true
+        }
+
+        fn list_voices(&self) -> Vec<VoiceInfo> {
+            Vec::new()
+        }
+
+        fn synthesize(
+            &self,
+            text: &str,
+            output_path: &str,
+            profile: &VoiceProfile,
+        ) -> Result<TtsResult, TtsError> {
+            self.said.lock().unwrap().push(text.to_string());
+            MockBackend::new().synthesize(text, output_path, profile)
+        }
+    }
+
+    #[test]
+    fn test_phoneme_is_one_token_and_keeps_timings() {
+        let path = std::env::temp_dir().join(format!("ssml_phoneme_{}.wav", std::process::id()));
+        let inner = Arc::new(PhonemeBackend::default());
+        let backend = SsmlBackend::new(inner.clone());
+        let result = backend
+            .synthesize(
+                r#"<speak>Say <phoneme ph="təˈmɑːtoʊ">tomato</phoneme> twice.</speak>"#,
+                &path.to_string_lossy(),
+                &VoiceProfile::default(),
+            )
+            .unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        let said = inner.said.lock().unwrap().clone();
+        assert_eq!(said, vec!["Say [[t@'mA:toU]] twice.".to_string()]);
+        let words: Vec<&str> = result.word_timings.iter().map(|t| t.word.as_str()).collect();
+        assert_eq!(words, vec!["Say", "tomato", "twice."]);
+
+        // Each display word keeps the timing of the token spoken for it
+        let spoken_path = std::env::temp_dir().join(format!("ssml_spoken_{}.wav", std::process::id()));
+        let spoken = MockBackend::new()
+            .synthesize(&said[0], &spoken_path.to_string_lossy(), &VoiceProfile::default())
+            .unwrap();
+        let _ = std::fs::remove_file(&spoken_path);
+        assert_eq!(spoken.word_timings.len(), 3);
+        for (shown, heard) in result.word_timings.iter().zip(&spoken.word_timings) {
+            assert!(
+                shown.start_ms.abs_diff(heard.start_ms) <= EDGE_MARGIN_MS
+                    && shown.end_ms.abs_diff(heard.end_ms) <= EDGE_MARGIN_MS,
+                "{} at {}-{}ms, spoken at {}-{}ms",
+                shown.word, shown.start_ms, shown.end_ms, heard.start_ms, heard.end_ms
+            );
+        }
+    }
+
+    #[test]
+    fn test_piper_reads_phoneme_text() {
+        let segments = segments(r#"<speak><phoneme ph="təˈmɑːtoʊ">tomato</phoneme></speak>"#, false).unwrap();
+        let spoken: Vec<String> = segments
+            .iter()
+            .flat_map(|segment| match segment {
+                Segment::Speech { units, .. } => units.iter().flat_map(|u| u.spoken.clone()).collect(),
+                Segment::Silence(_) => Vec::new(),
+            })
+            .collect();
+        assert_eq!(spoken, vec!["tomato"]);
+    }
+}
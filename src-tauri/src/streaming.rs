@@ -0,0 +1,410 @@
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::audio::AudioController;
+use crate::synthesis::TextChunk;
+use crate::tts_engine::{RawAudio, TtsBackend, VoiceProfile, WordTiming};
+use crate::wav::ms_to_samples;
+
+/// Streaming uses short chunks so the first one is ready quickly
+pub const STREAM_CHUNK_CHARS: usize = 240;
+/// How much synthesized audio may sit ahead of the playhead
+pub const DEFAULT_LOOKAHEAD_MS: u64 = 30_000;
+const BACKPRESSURE_POLL: Duration = Duration::from_millis(100);
+
+/// Where streamed chunks are played
+pub trait PlaybackSink: Send + 'static {
+    /// Current playhead position in milliseconds of audio
+    fn position_ms(&self) -> u64;
+
+    fn append(&self, audio: RawAudio) -> Result<(), String>;
+
+    /// Called once no more chunks will be appended
+    fn finish(&self);
+}
+
+impl PlaybackSink for AudioController {
+    fn position_ms(&self) -> u64 {
+        self.get_state().position_ms
+    }
+
+    fn append(&self, audio: RawAudio) -> Result<(), String> {
+        AudioController::append(self, audio.samples, audio.channels, audio.sample_rate)
+            .map_err(|e| e.message)
+    }
+
+    fn finish(&self) {
+        let _ = self.end_stream();
+    }
+}
+
+/// A chunk that has been synthesized and queued for playback
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StreamedChunk {
+    #[serde(flatten)]
+    pub chunk: TextChunk,
+    pub offset_ms: u64,
+    pub duration_ms: u64,
+    /// Timings relative to the start of the stream
+    pub word_timings: Vec<WordTiming>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StreamEvent {
+    Chunk(StreamedChunk),
+    Finished { cancelled: bool, duration_ms: u64 },
+}
+
+/// Handle to a running stream; cancelling stops it before the next chunk is
+/// synthesized or appended
+pub struct StreamHandle {
+    cancelled: Arc<AtomicBool>,
+    _thread: JoinHandle<()>,
+}
+
+impl StreamHandle {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+}
+
+impl Drop for StreamHandle {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+/// Synthesize chunks in reading order on a background thread, appending
+/// each one to `sink`, after its pause, as soon as it is ready. Synthesis
+/// pauses while more than `lookahead_ms` of audio is queued ahead of the
+/// playhead.
+pub fn start_stream<S, F>(
+    backend: Arc<dyn TtsBackend>,
+    chunks: Vec<TextChunk>,
+    profile: VoiceProfile,
+    sink: S,
+    lookahead_ms: u64,
+    on_event: F,
+) -> StreamHandle
+where
+    S: PlaybackSink,
+    F: Fn(StreamEvent) + Send + 'static,
+{
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let flag = cancelled.clone();
+
+    let thread = thread::spawn(move || {
+        let mut queued_ms = 0.0f64;
+
+        for chunk in chunks {
+            // Back-pressure: wait for the playhead to catch up
+            while !flag.load(Ordering::SeqCst)
+                && queued_ms as u64 > sink.position_ms() + lookahead_ms
+            {
+                thread::sleep(BACKPRESSURE_POLL);
+            }
+            if flag.load(Ordering::SeqCst) {
+                break;
+            }
+
+            let synthesized = backend.synthesize_raw(&chunk.spoken_text(), &profile);
+            // A stream cancelled during synthesis mustn't append to whatever
+            // plays next
+            if flag.load(Ordering::SeqCst) {
+                break;
+            }
+            let streamed = match synthesized {
+                Ok(audio) => {
+                    // No pause before the first audio
+                    let pause_ms = if queued_ms > 0.0 { chunk.pause_ms } else { 0 };
+                    let (audio, pause_ms) = with_leading_silence(audio, pause_ms);
+                    let offset_ms = (queued_ms + pause_ms).round() as u64;
+                    let duration_ms = audio.duration_ms();
+                    let word_timings = audio
+                        .word_timings
+                        .iter()
+                        .map(|t| WordTiming {
+                            word: t.word.clone(),
+                            start_ms: offset_ms + t.start_ms,
+                            end_ms: offset_ms + t.end_ms,
+                        })
+                        .collect();
+                    match sink.append(audio) {
+                        Ok(()) => {
+                            queued_ms += duration_ms;
+                            StreamedChunk {
+                                chunk,
+                                offset_ms,
+                                duration_ms: (duration_ms - pause_ms).round() as u64,
+                                word_timings,
+                                error: None,
+                            }
+                        }
+                        Err(e) => skipped_chunk(chunk, queued_ms.round() as u64, e),
+                    }
+                }
+                Err(e) => skipped_chunk(chunk, queued_ms.round() as u64, e.message),
+            };
+            on_event(StreamEvent::Chunk(streamed));
+        }
+
+        sink.finish();
+        on_event(StreamEvent::Finished {
+            cancelled: flag.load(Ordering::SeqCst),
+            duration_ms: queued_ms.round() as u64,
+        });
+    });
+
+    StreamHandle {
+        cancelled,
+        _thread: thread,
+    }
+}
+
+/// Prepend `pause_ms` of silence, returning the exact length added
+fn with_leading_silence(mut audio: RawAudio, pause_ms: u64) -> (RawAudio, f64) {
+    let frames = ms_to_samples(pause_ms, audio.sample_rate);
+    let channels = audio.channels.max(1) as usize;
+    audio
+        .samples
+        .splice(0..0, std::iter::repeat_n(0, frames * channels));
+    let added_ms = frames as f64 * 1000.0 / audio.sample_rate.max(1) as f64;
+    (audio, added_ms)
+}
+
+/// A chunk that produced no audio; its words get zero-length timings
+fn skipped_chunk(chunk: TextChunk, offset_ms: u64, error: String) -> StreamedChunk {
+    let word_timings = chunk
+        .words()
+        .into_iter()
+        .map(|word| WordTiming {
+            word,
+            start_ms: offset_ms,
+            end_ms: offset_ms,
+        })
+        .collect();
+    StreamedChunk {
+        chunk,
+        offset_ms,
+        duration_ms: 0,
+        word_timings,
+        error: Some(error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::SynthesisCache;
+    use crate::dialogue::DialogueVoices;
+    use crate::mock_engine::MockBackend;
+    use crate::pauses::{plan_chunks, PausePolicy};
+    use crate::tts_engine::{wrap_backend, BackendKind, TtsError, TtsErrorKind, TtsResult, VoiceInfo};
+    use std::sync::atomic::{AtomicU64, AtomicUsize};
+    use std::sync::mpsc;
+
+    /// An engine that can only synthesize into memory, counting requests
+    #[derive(Default)]
+    struct RawOnlyBackend {
+        requests: AtomicUsize,
+    }
+
+    impl TtsBackend for RawOnlyBackend {
+        fn kind(&self) -> BackendKind {
+            BackendKind::Mock
+        }
+
+        fn is_available(&self) -> bool {
+            true
+        }
+
+        fn list_voices(&self) -> Vec<VoiceInfo> {
+            Vec::new()
+        }
+
+        fn synthesize(&self, _: &str, _: &str, _: &VoiceProfile) -> Result<TtsResult, TtsError> {
+            Err(TtsError::new(TtsErrorKind::Other, "streamed through a file"))
+        }
+
+        fn synthesize_raw(&self, text: &str, profile: &VoiceProfile) -> Result<RawAudio, TtsError> {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+            MockBackend::new().synthesize_raw(text, profile)
+        }
+    }
+
+    /// Records appended audio; the playhead is moved by the test
+    #[derive(Clone, Default)]
+    struct TestSink {
+        position_ms: Arc<AtomicU64>,
+        appended: Arc<AtomicU64>,
+    }
+
+    impl PlaybackSink for TestSink {
+        fn position_ms(&self) -> u64 {
+            self.position_ms.load(Ordering::SeqCst)
+        }
+
+        fn append(&self, _audio: RawAudio) -> Result<(), String> {
+            self.appended.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn finish(&self) {}
+    }
+
+    fn chunks(count: usize) -> Vec<TextChunk> {
+        let paragraphs: Vec<String> = (0..count).map(|i| format!("Paragraph {} is here.", i)).collect();
+        plan_chunks(&paragraphs, STREAM_CHUNK_CHARS, &PausePolicy::default())
+    }
+
+    #[test]
+    fn test_stream_emits_chunks_in_order() {
+        let (tx, rx) = mpsc::channel();
+        let sink = TestSink::default();
+        let _handle = start_stream(
+            Arc::new(MockBackend::new()),
+            chunks(3),
+            VoiceProfile::default(),
+            sink.clone(),
+            DEFAULT_LOOKAHEAD_MS,
+            move |event| tx.send(event).unwrap(),
+        );
+
+        let events: Vec<StreamEvent> = rx.iter().collect();
+        assert_eq!(events.len(), 4);
+
+        // Each chunk starts after the previous one and the paragraph pause
+        let mut end_of_previous = 0;
+        for (index, event) in events[..3].iter().enumerate() {
+            let StreamEvent::Chunk(chunk) = event else {
+                panic!("expected a chunk event");
+            };
+            let expected_offset = end_of_previous + chunk.chunk.pause_ms;
+            assert_eq!(chunk.chunk.index, index);
+            assert_eq!(chunk.chunk.pause_ms, if index == 0 { 0 } else { 800 });
+            assert_eq!(chunk.offset_ms, expected_offset);
+            assert_eq!(chunk.word_timings[0].start_ms, expected_offset);
+            end_of_previous = expected_offset + chunk.duration_ms;
+        }
+        assert!(matches!(events[3], StreamEvent::Finished { cancelled: false, .. }));
+        assert_eq!(sink.appended.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_stream_waits_for_playhead_and_cancels() {
+        let (tx, rx) = mpsc::channel();
+        let sink = TestSink::default();
+        // Each mock paragraph is over a second long, so a zero lookahead
+        // lets exactly one chunk through until the playhead moves
+        let handle = start_stream(
+            Arc::new(MockBackend::new()),
+            chunks(50),
+            VoiceProfile::default(),
+            sink.clone(),
+            0,
+            move |event| {
+                let _ = tx.send(event);
+            },
+        );
+
+        assert!(matches!(rx.recv().unwrap(), StreamEvent::Chunk(_)));
+        thread::sleep(BACKPRESSURE_POLL * 3);
+        assert_eq!(sink.appended.load(Ordering::SeqCst), 1);
+
+        sink.position_ms.store(10_000, Ordering::SeqCst);
+        assert!(matches!(rx.recv().unwrap(), StreamEvent::Chunk(_)));
+
+        handle.cancel();
+        let last = rx.iter().last().unwrap();
+        assert!(matches!(last, StreamEvent::Finished { cancelled: true, .. }));
+        assert!(sink.appended.load(Ordering::SeqCst) < 50);
+    }
+
+    #[test]
+    fn test_chunk_finished_after_cancel_is_dropped() {
+        /// Takes long enough to be cancelled mid-chunk
+        struct SlowBackend;
+
+        impl TtsBackend for SlowBackend {
+            fn kind(&self) -> BackendKind {
+                BackendKind::Mock
+            }
+
+            fn is_available(&self) -> bool {
+                true
+            }
+
+            fn list_voices(&self) -> Vec<VoiceInfo> {
+                Vec::new()
+            }
+
+            fn synthesize(
+                &self,
+                text: &str,
+                path: &str,
+                profile: &VoiceProfile,
+            ) -> Result<TtsResult, TtsError> {
+                thread::sleep(Duration::from_millis(300));
+                MockBackend::new().synthesize(text, path, profile)
+            }
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let sink = TestSink::default();
+        let handle = start_stream(
+            Arc::new(SlowBackend),
+            chunks(2),
+            VoiceProfile::default(),
+            sink.clone(),
+            DEFAULT_LOOKAHEAD_MS,
+            move |event| tx.send(event).unwrap(),
+        );
+        thread::sleep(Duration::from_millis(100));
+        handle.cancel();
+
+        let events: Vec<StreamEvent> = rx.iter().collect();
+        assert!(matches!(events[..], [StreamEvent::Finished { cancelled: true, .. }]));
+        assert_eq!(sink.appended.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_stream_reaches_raw_synthesis_through_every_wrapper() {
+        let engine = Arc::new(RawOnlyBackend::default());
+        let dir = std::env::temp_dir().join(format!("stream_cache_{}", std::process::id()));
+        let cache = Arc::new(SynthesisCache::new(dir.clone(), u64::MAX));
+        let dialogue = DialogueVoices {
+            enabled: true,
+            ..Default::default()
+        };
+        let paragraphs = vec!["\"Wait,\" she said.".to_string(), "Then nothing.".to_string()];
+        let chunks = plan_chunks(&paragraphs, STREAM_CHUNK_CHARS, &PausePolicy::default());
+
+        let mut errors = Vec::new();
+        for _ in 0..2 {
+            let (tx, rx) = mpsc::channel();
+            let _handle = start_stream(
+                wrap_backend(engine.clone(), Some(cache.clone()), dialogue.clone()),
+                chunks.clone(),
+                VoiceProfile::default(),
+                TestSink::default(),
+                DEFAULT_LOOKAHEAD_MS,
+                move |event| tx.send(event).unwrap(),
+            );
+            errors.extend(rx.iter().filter_map(|event| match event {
+                StreamEvent::Chunk(chunk) => chunk.error,
+                StreamEvent::Finished { .. } => None,
+            }));
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert!(errors.is_empty(), "{:?}", errors);
+        // The quote and the narration around it, then the second paragraph,
+        // each synthesized once; the second stream is served from the cache
+        assert_eq!(engine.requests.load(Ordering::SeqCst), 3);
+    }
+}
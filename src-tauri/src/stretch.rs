@@ -0,0 +1,322 @@
+use rodio::source::SeekError;
+use rodio::Source;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+pub const MIN_SPEED: f32 = 0.25;
+pub const MAX_SPEED: f32 = 4.0;
+
+/// Length of each analysis frame; long enough to hold a couple of pitch
+/// periods of a low voice
+const FRAME_MS: u32 = 30;
+/// How far a frame may be moved from its nominal position to line up with
+/// the previous one
+const TOLERANCE_MS: u32 = 8;
+
+/// Playback speed shared between the audio thread and the sources it has
+/// queued, so a change applies to audio already in the sink
+#[derive(Debug, Clone)]
+pub struct StretchRate(Arc<AtomicU32>);
+
+impl Default for StretchRate {
+    fn default() -> Self {
+        StretchRate::new(1.0)
+    }
+}
+
+impl StretchRate {
+    pub fn new(speed: f32) -> Self {
+        StretchRate(Arc::new(AtomicU32::new(speed.to_bits())))
+    }
+
+    pub fn get(&self) -> f32 {
+        f32::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    pub fn set(&self, speed: f32) {
+        self.0.store(speed.clamp(MIN_SPEED, MAX_SPEED).to_bits(), Ordering::Relaxed);
+    }
+}
+
+/// Changes the speed of a source without changing its pitch, using WSOLA:
+/// Hann-windowed frames are overlap-added at a fixed output hop while the
+/// input is read faster or slower, each frame nudged to where it best
+/// continues the waveform of the one before. Passes samples through
+/// untouched until the speed first moves off 1.0.
+pub struct TimeStretch<S> {
+    inner: S,
+    rate: StretchRate,
+    channels: usize,
+    sample_rate: u32,
+    frame_len: usize,
+    hop: usize,
+    tolerance: usize,
+    window: Vec<f32>,
+    /// Interleaved input not yet discarded
+    input: Vec<f32>,
+    input_done: bool,
+    /// Where the next frame would start without alignment, in input frames
+    nominal: f64,
+    /// Where the frame added last would carry on in the input, which the
+    /// next frame should sound like
+    continuation: Option<usize>,
+    /// Overlap-add accumulator, one frame long
+    overlap: Vec<f32>,
+    ready: Vec<f32>,
+    ready_pos: usize,
+    finished: bool,
+    /// Stretching has started; until then samples are passed through
+    active: bool,
+    /// Samples passed through, to switch on a frame boundary
+    passed: usize,
+}
+
+impl<S> TimeStretch<S>
+where
+    S: Source<Item = i16>,
+{
+    pub fn new(inner: S, rate: StretchRate) -> Self {
+        let channels = inner.channels().max(1) as usize;
+        let sample_rate = inner.sample_rate();
+        let frame_len = ((sample_rate * FRAME_MS / 1000) as usize).max(4) & !1;
+        let hop = frame_len / 2;
+        let tolerance = (sample_rate * TOLERANCE_MS / 1000) as usize;
+        // Periodic Hann windows at half overlap sum to one
+        let window = (0..frame_len)
+            .map(|i| 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / frame_len as f32).cos())
+            .collect();
+        TimeStretch {
+            inner,
+            rate,
+            channels,
+            sample_rate,
+            frame_len,
+            hop,
+            tolerance,
+            window,
+            input: Vec::new(),
+            input_done: false,
+            nominal: 0.0,
+            continuation: None,
+            overlap: vec![0.0; frame_len * channels],
+            ready: Vec::new(),
+            ready_pos: 0,
+            finished: false,
+            active: false,
+            passed: 0,
+        }
+    }
+
+    fn input_frames(&self) -> usize {
+        self.input.len() / self.channels
+    }
+
+    /// Read input until `frames` are buffered or the source ends
+    fn fill(&mut self, frames: usize) {
+        while !self.input_done && self.input_frames() < frames {
+            match self.inner.next() {
+                Some(sample) => self.input.push(sample as f32),
+                None => self.input_done = true,
+            }
+        }
+    }
+
+    /// Sample of the mono mix at `frame`, silent past the end of the input
+    fn mono(&self, frame: usize) -> f32 {
+        let start = frame * self.channels;
+        self.input
+            .get(start..start + self.channels)
+            .map(|samples| samples.iter().sum())
+            .unwrap_or(0.0)
+    }
+
+    /// The candidate start in `lo..=hi` whose opening best matches `target`
+    fn best_match(&self, lo: usize, hi: usize, target: usize) -> usize {
+        let reference: Vec<f32> = (0..self.hop).map(|i| self.mono(target + i)).collect();
+        let mut best = (lo, f32::MIN);
+        for candidate in lo..=hi {
+            let mut dot = 0.0;
+            let mut energy = 1e-6;
+            for (i, r) in reference.iter().enumerate() {
+                let x = self.mono(candidate + i);
+                dot += x * r;
+                energy += x * x;
+            }
+            let score = dot / energy.sqrt();
+            if score > best.1 {
+                best = (candidate, score);
+            }
+        }
+        best.0
+    }
+
+    /// Add the next frame and move one hop of finished output to `ready`
+    fn step(&mut self) {
+        let channels = self.channels;
+        let position = match self.continuation {
+            None => {
+                self.fill(self.frame_len);
+                0
+            }
+            Some(continuation) => {
+                let nominal = self.nominal.round() as usize;
+                let lo = nominal.saturating_sub(self.tolerance);
+                let hi = nominal + self.tolerance;
+                self.fill((hi + self.frame_len).max(continuation + self.hop));
+                let advance = self.hop as f64 * self.rate.get() as f64;
+                if self.input_done && (self.nominal + advance) as usize >= self.input_frames() {
+                    // Out of input: the tail still in the accumulator is the end
+                    self.ready = self.overlap[..(self.frame_len - self.hop) * channels].to_vec();
+                    self.ready_pos = 0;
+                    self.finished = true;
+                    return;
+                }
+                self.best_match(lo, hi, continuation)
+            }
+        };
+
+        for frame in 0..self.frame_len {
+            // The first frame has nothing to overlap, so it starts at full level
+            let gain = if self.continuation.is_none() && frame < self.hop {
+                1.0
+            } else {
+                self.window[frame]
+            };
+            for channel in 0..channels {
+                let sample = self
+                    .input
+                    .get((position + frame) * channels + channel)
+                    .copied()
+                    .unwrap_or(0.0);
+                self.overlap[frame * channels + channel] += gain * sample;
+            }
+        }
+
+        let hop_samples = self.hop * channels;
+        self.ready = self.overlap[..hop_samples].to_vec();
+        self.ready_pos = 0;
+        self.overlap.copy_within(hop_samples.., 0);
+        let len = self.overlap.len();
+        self.overlap[len - hop_samples..].fill(0.0);
+
+        let continuation = position + self.hop;
+        self.nominal += self.hop as f64 * self.rate.get() as f64;
+
+        // Drop input no later frame can reach
+        let keep_from = (self.nominal as usize)
+            .saturating_sub(self.tolerance)
+            .min(continuation)
+            .min(self.input_frames());
+        self.input.drain(..keep_from * channels);
+        self.nominal -= keep_from as f64;
+        self.continuation = Some(continuation - keep_from);
+    }
+
+    fn reset(&mut self) {
+        self.input.clear();
+        self.input_done = false;
+        self.nominal = 0.0;
+        self.continuation = None;
+        self.overlap.fill(0.0);
+        self.ready.clear();
+        self.ready_pos = 0;
+        self.finished = false;
+    }
+}
+
+impl<S> Iterator for TimeStretch<S>
+where
+    S: Source<Item = i16>,
+{
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        if !self.active {
+            if self.rate.get() == 1.0 || !self.passed.is_multiple_of(self.channels) {
+                self.passed += 1;
+                return self.inner.next();
+            }
+            self.active = true;
+        }
+        loop {
+            if let Some(&sample) = self.ready.get(self.ready_pos) {
+                self.ready_pos += 1;
+                return Some(sample.round().clamp(i16::MIN as f32, i16::MAX as f32) as i16);
+            }
+            if self.finished {
+                return None;
+            }
+            self.step();
+        }
+    }
+}
+
+impl<S> Source for TimeStretch<S>
+where
+    S: Source<Item = i16>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels as u16
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.inner.try_seek(pos)?;
+        self.reset();
+        self.passed = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    /// One second of a 440 Hz tone
+    fn tone() -> SamplesBuffer<i16> {
+        let samples = (0..16000)
+            .map(|i| ((i as f32 * 440.0 * 2.0 * std::f32::consts::PI / 16000.0).sin() * 10000.0) as i16)
+            .collect::<Vec<_>>();
+        SamplesBuffer::new(1, 16000, samples)
+    }
+
+    fn zero_crossings(samples: &[i16]) -> usize {
+        samples.windows(2).filter(|pair| (pair[0] < 0) != (pair[1] < 0)).count()
+    }
+
+    #[test]
+    fn test_stretch_keeps_pitch() {
+        for speed in [0.5, 1.5, 3.0] {
+            let output: Vec<i16> = TimeStretch::new(tone(), StretchRate::new(speed)).collect();
+            let expected = 16000.0 / speed;
+            let length = output.len() as f32;
+            assert!((length - expected).abs() < expected * 0.05, "{}x gave {} samples", speed, length);
+
+            // 440 Hz crosses zero 880 times a second, whatever the length
+            let crossings_per_second = zero_crossings(&output) as f32 * 16000.0 / length;
+            assert!(
+                (crossings_per_second - 880.0).abs() < 880.0 * 0.05,
+                "{}x gave {} crossings per second",
+                speed,
+                crossings_per_second
+            );
+        }
+
+        // At normal speed the source is untouched
+        let output: Vec<i16> = TimeStretch::new(tone(), StretchRate::new(1.0)).collect();
+        assert_eq!(output, tone().collect::<Vec<_>>());
+    }
+}
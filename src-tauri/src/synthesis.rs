@@ -0,0 +1,731 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+use crate::jobs::CancelToken;
+use crate::loudness::{level_wav, Loudness, LoudnessSettings};
+use crate::ssml::{display_words, is_ssml};
+use crate::tts_engine::{TtsBackend, TtsError, TtsErrorKind, VoiceProfile, WordTiming};
+use crate::wav::{concat_wavs, frames_to_ms};
+
+/// Paragraphs longer than this are split at sentence boundaries
+pub const MAX_CHUNK_CHARS: usize = 600;
+const MANIFEST_FILE: &str = "manifest.json";
+const COMBINED_FILE: &str = "audio.wav";
+
+/// A piece of the document synthesized as one unit
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TextChunk {
+    pub index: usize,
+    pub paragraph: usize,
+    /// Index of the chunk's first word within the whole document
+    pub first_word: usize,
+    pub text: String,
+    /// Silence inserted before the chunk's audio
+    pub pause_ms: u64,
+    /// Starts inside a quotation opened by an earlier chunk
+    #[serde(default)]
+    pub quote_open: bool,
+    /// Number of display words, counted once when the chunk is planned
+    #[serde(default)]
+    pub word_count: usize,
+}
+
+impl TextChunk {
+    /// Text sent to the engine. A chunk continuing a quotation gets its
+    /// opening mark back, so it is still read as dialogue.
+    pub fn spoken_text(&self) -> String {
+        if self.quote_open {
+            format!("\u{201c}{}", self.text)
+        } else {
+            self.text.clone()
+        }
+    }
+
+    /// Words as shown to the reader; markup in SSML chunks is left out
+    pub fn words(&self) -> Vec<String> {
+        if is_ssml(&self.text) {
+            if let Ok(words) = display_words(&self.text) {
+                return words;
+            }
+        }
+        self.text.split_whitespace().map(str::to_string).collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChunkStatus {
+    Pending,
+    Done,
+    Failed,
+}
+
+/// Synthesis outcome for one chunk
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChunkEntry {
+    #[serde(flatten)]
+    pub chunk: TextChunk,
+    pub status: ChunkStatus,
+    pub audio_path: Option<String>,
+    /// Timings relative to the start of the chunk
+    pub word_timings: Vec<WordTiming>,
+    /// Start of the chunk within the combined audio
+    pub offset_ms: u64,
+    pub duration_ms: u64,
+    pub error: Option<String>,
+    pub error_kind: Option<TtsErrorKind>,
+}
+
+/// Every chunk of a document and the combined audio built from them
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SynthesisManifest {
+    pub audio_path: String,
+    pub chunks: Vec<ChunkEntry>,
+    /// Timings for every word of the document, relative to the combined audio.
+    /// Words of failed chunks get zero-length timings so indices still line up.
+    pub word_timings: Vec<WordTiming>,
+    pub duration_ms: u64,
+    pub stats: SynthesisStats,
+    /// Level of the combined audio, for normalizing playback
+    #[serde(default)]
+    pub loudness: Option<Loudness>,
+    /// The loudness settings were applied to the combined audio when it
+    /// was written, so playback shouldn't apply them again
+    #[serde(default)]
+    pub leveled: bool,
+}
+
+/// How a document is synthesized and written
+#[derive(Debug, Clone)]
+pub struct SynthesisOptions {
+    /// Chunks synthesized at once
+    pub concurrency: usize,
+    /// Normalization and compression applied to the combined audio
+    pub loudness: LoudnessSettings,
+}
+
+impl Default for SynthesisOptions {
+    fn default() -> Self {
+        SynthesisOptions {
+            concurrency: default_concurrency(),
+            loudness: LoudnessSettings::default(),
+        }
+    }
+}
+
+/// Throughput of a synthesis run
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SynthesisStats {
+    pub chunks: usize,
+    /// Worker threads that ran, at most one per chunk
+    pub concurrency: usize,
+    pub audio_ms: u64,
+    pub wall_ms: u64,
+    /// Seconds of audio produced per second of wall-clock time
+    pub realtime_factor: f64,
+}
+
+impl SynthesisStats {
+    fn new(chunks: usize, concurrency: usize, audio_ms: u64, started: Instant) -> Self {
+        let wall_ms = started.elapsed().as_millis() as u64;
+        SynthesisStats {
+            chunks,
+            concurrency,
+            audio_ms,
+            wall_ms,
+            realtime_factor: audio_ms as f64 / wall_ms.max(1) as f64,
+        }
+    }
+}
+
+/// How far a document's synthesis has got
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SynthesisProgress {
+    pub completed: usize,
+    pub total: usize,
+    /// Index of the chunk that just finished
+    pub current_chunk: usize,
+    /// Share of the document's text synthesized so far
+    pub percent: f64,
+    pub elapsed_ms: u64,
+    /// Estimated time left at the throughput so far
+    pub eta_ms: u64,
+}
+
+/// Counts finished chunks by their share of the text, since chunk lengths vary
+struct ProgressTracker {
+    total: usize,
+    total_chars: usize,
+    started: Instant,
+    done: Mutex<(usize, usize)>,
+}
+
+impl ProgressTracker {
+    fn new(chunks: &[TextChunk]) -> Self {
+        ProgressTracker {
+            total: chunks.len(),
+            total_chars: chunks.iter().map(|c| c.text.len()).sum(),
+            started: Instant::now(),
+            done: Mutex::new((0, 0)),
+        }
+    }
+
+    fn record(&self, chunk: &TextChunk) -> SynthesisProgress {
+        let (completed, done_chars) = {
+            let mut done = self.done.lock().unwrap();
+            done.0 += 1;
+            done.1 += chunk.text.len();
+            *done
+        };
+        let fraction = done_chars as f64 / self.total_chars.max(1) as f64;
+        let elapsed_ms = self.started.elapsed().as_millis() as u64;
+        let eta_ms = if fraction > 0.0 {
+            (elapsed_ms as f64 * (1.0 - fraction) / fraction).round() as u64
+        } else {
+            0
+        };
+
+        SynthesisProgress {
+            completed,
+            total: self.total,
+            current_chunk: chunk.index,
+            percent: fraction * 100.0,
+            elapsed_ms,
+            eta_ms,
+        }
+    }
+}
+
+/// Default number of concurrent synthesis workers: one per CPU core
+pub fn default_concurrency() -> usize {
+    thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+}
+
+/// Split text into sentences after `.`, `!` or `?`, keeping trailing quotes
+pub fn split_sentences(text: &str) -> Vec<String> {
+    let mut sentences = Vec::new();
+    let mut current: Vec<&str> = Vec::new();
+
+    for word in text.split_whitespace() {
+        current.push(word);
+        let end = word.trim_end_matches(['"', '\'', ')', ']', '\u{201d}', '\u{2019}']);
+        if end.ends_with(['.', '!', '?']) {
+            sentences.push(current.join(" "));
+            current.clear();
+        }
+    }
+    if !current.is_empty() {
+        sentences.push(current.join(" "));
+    }
+
+    sentences
+}
+
+/// Split paragraphs into chunks: one per paragraph, with long paragraphs
+/// packed sentence by sentence into chunks of at most `max_chars`
+pub fn split_into_chunks(paragraphs: &[String], max_chars: usize) -> Vec<TextChunk> {
+    let mut chunks: Vec<TextChunk> = Vec::new();
+    let mut word_index = 0;
+
+    for (paragraph, text) in paragraphs.iter().enumerate() {
+        let mut pieces: Vec<String> = Vec::new();
+        for sentence in split_sentences(text) {
+            match pieces.last_mut() {
+                Some(last) if last.len() + 1 + sentence.len() <= max_chars => {
+                    last.push(' ');
+                    last.push_str(&sentence);
+                }
+                _ => pieces.push(sentence),
+            }
+        }
+
+        for text in pieces {
+            let word_count = text.split_whitespace().count();
+            chunks.push(TextChunk {
+                index: chunks.len(),
+                paragraph,
+                first_word: word_index,
+                text,
+                pause_ms: 0,
+                quote_open: false,
+                word_count,
+            });
+            word_index += word_count;
+        }
+    }
+
+    chunks
+}
+
+fn chunk_file(out_dir: &Path, index: usize) -> PathBuf {
+    out_dir.join(format!("chunk_{:05}.wav", index))
+}
+
+/// Synthesize one chunk into its own WAV file
+pub fn synthesize_chunk(
+    backend: &dyn TtsBackend,
+    chunk: TextChunk,
+    out_dir: &Path,
+    profile: &VoiceProfile,
+) -> ChunkEntry {
+    let path = chunk_file(out_dir, chunk.index).to_string_lossy().to_string();
+    match backend.synthesize(&chunk.spoken_text(), &path, profile) {
+        Ok(result) => ChunkEntry {
+            chunk,
+            status: ChunkStatus::Done,
+            audio_path: Some(result.audio_path),
+            word_timings: result.word_timings,
+            offset_ms: 0,
+            duration_ms: result.duration_ms,
+            error: None,
+            error_kind: None,
+        },
+        Err(e) => {
+            let _ = fs::remove_file(&path);
+            ChunkEntry {
+                chunk,
+                status: ChunkStatus::Failed,
+                audio_path: None,
+                word_timings: Vec::new(),
+                offset_ms: 0,
+                duration_ms: 0,
+                error: Some(e.message),
+                error_kind: Some(e.kind),
+            }
+        }
+    }
+}
+
+/// Stitch synthesized chunks into one file, leveled with `loudness`, and
+/// fill in offsets and document-wide timings
+pub fn assemble_manifest(
+    mut chunks: Vec<ChunkEntry>,
+    out_dir: &Path,
+    loudness: &LoudnessSettings,
+) -> Result<SynthesisManifest, TtsError> {
+    if !chunks.is_empty() && chunks.iter().all(|c| c.status == ChunkStatus::Failed) {
+        return Err(TtsError::new(
+            chunks[0].error_kind.unwrap_or_default(),
+            chunks[0].error.clone().unwrap_or_default(),
+        ));
+    }
+
+    let audio_path = out_dir.join(COMBINED_FILE);
+    let done: Vec<&ChunkEntry> = chunks.iter().filter(|c| c.audio_path.is_some()).collect();
+    let inputs: Vec<PathBuf> = done
+        .iter()
+        .filter_map(|c| c.audio_path.as_ref().map(PathBuf::from))
+        .collect();
+    // No leading silence before the first audio
+    let gaps: Vec<u64> = done
+        .iter()
+        .enumerate()
+        .map(|(i, c)| if i == 0 { 0 } else { c.chunk.pause_ms })
+        .collect();
+    let layout = concat_wavs(&inputs, &gaps, &audio_path).map_err(|e| TtsError::new(TtsErrorKind::Io, e.message))?;
+    let (offsets, duration_ms) = layout.offsets_ms();
+
+    let mut offsets = offsets.into_iter().zip(layout.frames.iter());
+    let mut word_timings = Vec::new();
+    let mut end_of_previous = 0;
+    for entry in chunks.iter_mut() {
+        if entry.status == ChunkStatus::Done {
+            if let Some((offset, frames)) = offsets.next() {
+                entry.offset_ms = offset;
+                entry.duration_ms = frames_to_ms(*frames, layout.sample_rate);
+            }
+            word_timings.extend(entry.word_timings.iter().map(|t| WordTiming {
+                word: t.word.clone(),
+                start_ms: entry.offset_ms + t.start_ms,
+                end_ms: entry.offset_ms + t.end_ms,
+            }));
+            end_of_previous = entry.offset_ms + entry.duration_ms;
+        } else {
+            entry.offset_ms = end_of_previous;
+            word_timings.extend(entry.chunk.words().into_iter().map(|word| WordTiming {
+                word,
+                start_ms: end_of_previous,
+                end_ms: end_of_previous,
+            }));
+        }
+    }
+
+    // Audio that can't be measured is kept as it is
+    let measured = level_wav(&audio_path, loudness).ok().flatten();
+    Ok(SynthesisManifest {
+        audio_path: audio_path.to_string_lossy().to_string(),
+        chunks,
+        word_timings,
+        duration_ms,
+        stats: SynthesisStats::default(),
+        leveled: measured.is_some() && (loudness.normalize || loudness.compressor),
+        loudness: measured,
+    })
+}
+
+/// Save the manifest next to the audio
+fn write_manifest(out_dir: &Path, manifest: &SynthesisManifest) -> Result<(), TtsError> {
+    let json = serde_json::to_string_pretty(manifest).map_err(|e| {
+        TtsError::new(TtsErrorKind::Other, format!("Failed to serialize manifest: {}", e))
+    })?;
+    fs::write(out_dir.join(MANIFEST_FILE), json)
+        .map_err(|e| TtsError::new(TtsErrorKind::Io, format!("Failed to write manifest: {}", e)))
+}
+
+/// Remove audio and the manifest left over from a previous document
+pub fn reset_output_dir(out_dir: &Path) -> Result<(), TtsError> {
+    if out_dir.exists() {
+        fs::remove_dir_all(out_dir).map_err(|e| {
+            TtsError::new(TtsErrorKind::Io, format!("Failed to clear {}: {}", out_dir.display(), e))
+        })?;
+    }
+    fs::create_dir_all(out_dir).map_err(|e| {
+        TtsError::new(TtsErrorKind::Io, format!("Failed to create {}: {}", out_dir.display(), e))
+    })
+}
+
+/// Remove the output of every job under `root` but `keep`, once playback
+/// has moved to `keep`. A directory that can't be removed yet, e.g. because
+/// a file in it is still open, is left for a later call.
+pub fn remove_other_outputs(root: &Path, keep: &Path) {
+    let Ok(entries) = fs::read_dir(root) else {
+        return;
+    };
+    for entry in entries.filter_map(Result::ok) {
+        let path = entry.path();
+        if path != keep && path.is_dir() {
+            let _ = fs::remove_dir_all(&path);
+        }
+    }
+}
+
+/// Worker threads used for `chunks` chunks: never more than there are chunks
+fn worker_count(concurrency: usize, chunks: usize) -> usize {
+    concurrency.clamp(1, chunks.max(1))
+}
+
+/// Synthesize chunks on a pool of `concurrency` worker threads, each running
+/// its own engine process. `on_chunk` is called as each chunk finishes and
+/// workers stop taking chunks once `cancel` is cancelled. Entries are
+/// returned in reading order.
+pub fn synthesize_chunks(
+    backend: &dyn TtsBackend,
+    chunks: Vec<TextChunk>,
+    out_dir: &Path,
+    profile: &VoiceProfile,
+    concurrency: usize,
+    cancel: &CancelToken,
+    on_chunk: &(dyn Fn(&ChunkEntry) + Sync),
+) -> Vec<ChunkEntry> {
+    let workers = worker_count(concurrency, chunks.len());
+    let queue = Mutex::new(chunks.into_iter());
+    let (tx, rx) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            let tx = tx.clone();
+            let queue = &queue;
+            scope.spawn(move || loop {
+                if cancel.is_cancelled() {
+                    break;
+                }
+                let next = queue.lock().unwrap().next();
+                let Some(chunk) = next else {
+                    break;
+                };
+                let entry = synthesize_chunk(backend, chunk, out_dir, profile);
+                on_chunk(&entry);
+                if tx.send(entry).is_err() {
+                    break;
+                }
+            });
+        }
+    });
+    drop(tx);
+
+    let mut entries: Vec<ChunkEntry> = rx.into_iter().collect();
+    entries.sort_by_key(|entry| entry.chunk.index);
+    entries
+}
+
+/// Synthesize a document's chunks, reporting progress as they finish, and
+/// join them with each chunk's pause before it, leveling the result as
+/// `options` ask. A failing chunk is recorded
+/// in the manifest and skipped; only a document where every chunk fails is
+/// an error. If `cancel` is cancelled the partial output is removed.
+pub fn synthesize_document(
+    backend: &dyn TtsBackend,
+    chunks: Vec<TextChunk>,
+    out_dir: &Path,
+    profile: &VoiceProfile,
+    options: &SynthesisOptions,
+    cancel: &CancelToken,
+    on_progress: &(dyn Fn(SynthesisProgress) + Sync),
+) -> Result<SynthesisManifest, TtsError> {
+    let started = Instant::now();
+    reset_output_dir(out_dir)?;
+
+    let chunk_count = chunks.len();
+    let tracker = ProgressTracker::new(&chunks);
+    let workers = worker_count(options.concurrency, chunk_count);
+    let entries = synthesize_chunks(backend, chunks, out_dir, profile, workers, cancel, &|entry| {
+        on_progress(tracker.record(&entry.chunk))
+    });
+
+    if cancel.is_cancelled() {
+        let _ = fs::remove_dir_all(out_dir);
+        return Err(cancel.error());
+    }
+
+    let mut manifest = assemble_manifest(entries, out_dir, &options.loudness)?;
+    manifest.stats = SynthesisStats::new(chunk_count, workers, manifest.duration_ms, started);
+    write_manifest(out_dir, &manifest)?;
+    Ok(manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_engine::{mock_word_timings, MockBackend, MOCK_SAMPLE_RATE};
+    use crate::tts_engine::{BackendKind, TtsResult, VoiceInfo};
+    use crate::wav::{ms_to_samples, read_wav_samples};
+
+    /// Mock engine that refuses text containing "FAIL"
+    struct FlakyBackend(MockBackend);
+
+    impl TtsBackend for FlakyBackend {
+        fn kind(&self) -> BackendKind {
+            BackendKind::Mock
+        }
+
+        fn is_available(&self) -> bool {
+            true
+        }
+
+        fn list_voices(&self) -> Vec<VoiceInfo> {
+            Vec::new()
+        }
+
+        fn synthesize(
+            &self,
+            text: &str,
+            output_path: &str,
+            profile: &VoiceProfile,
+        ) -> Result<TtsResult, TtsError> {
+            if text.contains("FAIL") {
+                return Err(TtsError::new(TtsErrorKind::Other, "refused"));
+            }
+            self.0.synthesize(text, output_path, profile)
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("{}_{}", name, std::process::id()))
+    }
+
+    fn synthesize(
+        backend: &dyn TtsBackend,
+        paragraphs: &[String],
+        dir: &Path,
+        concurrency: usize,
+    ) -> Result<SynthesisManifest, TtsError> {
+        let profile = VoiceProfile::default();
+        let chunks = split_into_chunks(paragraphs, MAX_CHUNK_CHARS);
+        let options = SynthesisOptions {
+            concurrency,
+            ..Default::default()
+        };
+        synthesize_document(backend, chunks, dir, &profile, &options, &CancelToken::new(), &|_| {})
+    }
+
+    #[test]
+    fn test_split_sentences_keeps_quotes() {
+        let sentences = split_sentences("He said \"Stop.\" Then he left! Did he?");
+        assert_eq!(sentences, vec!["He said \"Stop.\"", "Then he left!", "Did he?"]);
+    }
+
+    #[test]
+    fn test_split_into_chunks_packs_sentences() {
+        let paragraphs = vec![
+            "One two. Three four. Five six.".to_string(),
+            "Seven.".to_string(),
+        ];
+        let chunks = split_into_chunks(&paragraphs, 20);
+        let texts: Vec<&str> = chunks.iter().map(|c| c.text.as_str()).collect();
+        assert_eq!(texts, vec!["One two. Three four.", "Five six.", "Seven."]);
+        assert_eq!(chunks[1].paragraph, 0);
+        assert_eq!(chunks[1].first_word, 4);
+        assert_eq!(chunks[2].paragraph, 1);
+        assert_eq!(chunks[2].first_word, 6);
+    }
+
+    #[test]
+    fn test_failed_chunk_keeps_word_indices() {
+        let dir = temp_dir("synthesis_failed_chunk");
+        let paragraphs = vec![
+            "First paragraph.".to_string(),
+            "This one will FAIL.".to_string(),
+            "Last words.".to_string(),
+        ];
+
+        let backend = FlakyBackend(MockBackend::new());
+        let manifest = synthesize(&backend, &paragraphs, &dir, 1).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let statuses: Vec<ChunkStatus> = manifest.chunks.iter().map(|c| c.status).collect();
+        assert_eq!(statuses, vec![ChunkStatus::Done, ChunkStatus::Failed, ChunkStatus::Done]);
+        assert_eq!(manifest.word_timings.len(), 8);
+
+        // "First" 200 + gap 40, "paragraph." 400 + gap 40 + sentence 200
+        let first_duration = 880;
+        assert_eq!(manifest.chunks[2].offset_ms, first_duration);
+        assert_eq!(manifest.word_timings[2].start_ms, first_duration);
+        assert_eq!(manifest.word_timings[2].end_ms, first_duration);
+        assert_eq!(manifest.word_timings[6].start_ms, first_duration);
+        assert_eq!(manifest.duration_ms, first_duration + manifest.chunks[2].duration_ms);
+    }
+
+    #[test]
+    fn test_parallel_synthesis_keeps_reading_order() {
+        let dir = temp_dir("synthesis_parallel");
+        let paragraphs: Vec<String> = (0..12)
+            .map(|i| format!("Paragraph number {} has {} words.", i, "some ".repeat(i)))
+            .collect();
+        let backend = MockBackend::new();
+
+        let sequential = synthesize(&backend, &paragraphs, &dir, 1).unwrap();
+        let parallel = synthesize(&backend, &paragraphs, &dir, 4).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let indices: Vec<usize> = parallel.chunks.iter().map(|c| c.chunk.index).collect();
+        assert_eq!(indices, (0..12).collect::<Vec<_>>());
+        assert_eq!(parallel.duration_ms, sequential.duration_ms);
+        assert_eq!(parallel.word_timings.len(), sequential.word_timings.len());
+        assert_eq!(parallel.stats.concurrency, 4);
+        assert_eq!(parallel.stats.audio_ms, parallel.duration_ms);
+    }
+
+    #[test]
+    fn test_cancel_stops_and_removes_partial_output() {
+        let dir = temp_dir("synthesis_cancel");
+        let paragraphs: Vec<String> = (0..10).map(|i| format!("Paragraph {}.", i)).collect();
+        let cancel = CancelToken::new();
+        let reports = Mutex::new(Vec::new());
+
+        let result = synthesize_document(
+            &MockBackend::new(),
+            split_into_chunks(&paragraphs, MAX_CHUNK_CHARS),
+            &dir,
+            &VoiceProfile::default(),
+            &SynthesisOptions::default(),
+            &cancel,
+            &|progress| {
+                reports.lock().unwrap().push(progress);
+                if reports.lock().unwrap().len() == 3 {
+                    cancel.cancel();
+                }
+            },
+        );
+
+        assert!(result.is_err());
+        assert!(!dir.exists());
+        let reports = reports.into_inner().unwrap();
+        assert_eq!(reports.len(), 3);
+        assert_eq!(reports[2].completed, 3);
+        assert_eq!(reports[2].total, 10);
+        assert!(reports[2].percent > 20.0 && reports[2].percent < 40.0);
+    }
+
+    #[test]
+    fn test_pauses_are_inserted_between_chunks() {
+        let dir = temp_dir("synthesis_pauses");
+        let paragraphs = vec!["First.".to_string(), "Second.".to_string()];
+        let mut chunks = split_into_chunks(&paragraphs, MAX_CHUNK_CHARS);
+        chunks[1].pause_ms = 800;
+
+        let manifest = synthesize_document(
+            &MockBackend::new(),
+            chunks,
+            &dir,
+            &VoiceProfile::default(),
+            &SynthesisOptions::default(),
+            &CancelToken::new(),
+            &|_| {},
+        )
+        .unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let first = &manifest.chunks[0];
+        let second = &manifest.chunks[1];
+        assert_eq!(second.offset_ms, first.duration_ms + 800);
+        assert_eq!(manifest.word_timings[1].start_ms, second.offset_ms);
+        assert_eq!(manifest.duration_ms, second.offset_ms + second.duration_ms);
+    }
+
+    #[test]
+    fn test_mock_document_end_to_end() {
+        let dir = temp_dir("synthesis_end_to_end");
+        let paragraphs = vec!["Hello there. How are you?".to_string(), "Fine.".to_string()];
+        let manifest = synthesize(&MockBackend::new(), &paragraphs, &dir, 8).unwrap();
+        let (spec, samples) = read_wav_samples(Path::new(&manifest.audio_path)).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(manifest.chunks.len(), 2);
+        assert_eq!(manifest.stats.concurrency, 2);
+        assert!(manifest.chunks.iter().all(|c| c.status == ChunkStatus::Done));
+
+        // Document timings are the mock's exact timings shifted by each chunk's offset
+        let profile = VoiceProfile::default();
+        let mut expected = Vec::new();
+        for entry in &manifest.chunks {
+            let (timings, duration_ms) = mock_word_timings(&entry.chunk.spoken_text(), &profile);
+            assert_eq!(entry.duration_ms, duration_ms);
+            let offset = entry.offset_ms;
+            expected.extend(
+                timings
+                    .into_iter()
+                    .map(|t| (t.word, t.start_ms + offset, t.end_ms + offset)),
+            );
+        }
+        let actual: Vec<(String, u64, u64)> = manifest
+            .word_timings
+            .iter()
+            .map(|t| (t.word.clone(), t.start_ms, t.end_ms))
+            .collect();
+        assert_eq!(actual, expected);
+
+        // The combined WAV is as long as the manifest says, with a tone under
+        // every word and silence between them
+        assert_eq!(spec.sample_rate, MOCK_SAMPLE_RATE);
+        let at = |ms: u64| ms_to_samples(ms, MOCK_SAMPLE_RATE);
+        assert_eq!(samples.len(), at(manifest.duration_ms));
+        for pair in manifest.word_timings.windows(2) {
+            let word = at(pair[0].start_ms)..at(pair[0].end_ms);
+            let gap = word.end..at(pair[1].start_ms);
+            assert!(samples[word].iter().any(|&s| s != 0));
+            assert!(samples[gap].iter().all(|&s| s == 0));
+        }
+    }
+
+    #[test]
+    fn test_each_job_keeps_its_own_output() {
+        let root = temp_dir("synthesis_jobs");
+        let paragraphs = vec!["Still playing.".to_string()];
+        let playing = synthesize(&MockBackend::new(), &paragraphs, &root.join("job_1"), 1).unwrap();
+        let next = synthesize(&MockBackend::new(), &paragraphs, &root.join("job_2"), 1).unwrap();
+        assert!(Path::new(&playing.audio_path).exists());
+
+        let next_dir = Path::new(&next.audio_path).parent().unwrap();
+        remove_other_outputs(&root, next_dir);
+        let playing_exists = Path::new(&playing.audio_path).exists();
+        let next_exists = Path::new(&next.audio_path).exists();
+        fs::remove_dir_all(&root).unwrap();
+
+        assert!(!playing_exists);
+        assert!(next_exists);
+    }
+}
@@ -1,7 +1,23 @@
 use serde::{Deserialize, Serialize};
-use std::io::Write;
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::alignment::{align_wav, align_words};
+use crate::cache::{CachedBackend, SynthesisCache};
+use crate::dialogue::{DialogueBackend, DialogueVoices};
+use crate::espeak_engine::EspeakBackend;
+use crate::jobs::CancelToken;
+use crate::mock_engine::MockBackend;
+use crate::piper_worker::{WorkerKey, WorkerPool};
+use crate::settings::{AppSettings, PIPER_PATH_ENV};
+use crate::ssml::SsmlBackend;
+use crate::wav::read_wav_samples;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WordTiming {
@@ -17,69 +33,602 @@ pub struct TtsResult {
     pub duration_ms: u64,
 }
 
+/// Synthesized 16-bit PCM kept in memory
+#[derive(Debug, Clone)]
+pub struct RawAudio {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub samples: Vec<i16>,
+    pub word_timings: Vec<WordTiming>,
+}
+
+impl RawAudio {
+    /// Duration in milliseconds, as a float so callers can sum without drift
+    pub fn duration_ms(&self) -> f64 {
+        let frames = self.samples.len() as f64 / self.channels.max(1) as f64;
+        frames * 1000.0 / self.sample_rate.max(1) as f64
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TtsError {
+    pub kind: TtsErrorKind,
     pub message: String,
 }
 
-/// Get the path to the Piper TTS executable
-fn get_piper_path() -> PathBuf {
-    // Look for piper in the resources directory
-    let exe_dir = std::env::current_exe()
-        .ok()
-        .and_then(|p| p.parent().map(|p| p.to_path_buf()))
-        .unwrap_or_else(|| PathBuf::from("."));
-    
-    // Try multiple locations (including dev mode paths)
-    let possible_paths = vec![
-        // Production: next to exe
-        exe_dir.join("piper").join("piper.exe"),
-        // Dev mode: project root (exe is in src-tauri/target/debug)
-        exe_dir.join("..").join("..").join("..").join("piper").join("piper.exe"),
-        exe_dir.join("..").join("..").join("..").join("..").join("piper").join("piper.exe"),
-        // Current working directory
-        PathBuf::from("piper").join("piper.exe"),
-        // Absolute fallback for this specific project
-        PathBuf::from("F:\\Programming\\PdfAudio\\piper\\piper.exe"),
-    ];
+/// What went wrong, so the UI can suggest a fix
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TtsErrorKind {
+    /// The engine executable is missing or couldn't be started
+    EngineNotFound,
+    /// No voice model, or the requested one isn't installed
+    MissingModel,
+    /// The model's `.onnx.json` config is missing or invalid
+    BadModelConfig,
+    /// espeak-ng phoneme data couldn't be loaded
+    MissingEspeakData,
+    /// ONNX Runtime failed to load or run the model
+    OnnxRuntime,
+    /// The voice profile asks for something the voice can't do
+    InvalidProfile,
+    /// SSML input is malformed or uses unsupported values
+    InvalidMarkup,
+    /// The engine took longer than the chunk's time limit
+    Timeout,
+    /// The engine exited or stopped responding mid-request
+    Crashed,
+    Cancelled,
+    /// Reading or writing audio files failed
+    Io,
+    #[default]
+    Other,
+}
+
+impl TtsErrorKind {
+    /// Whether trying again could succeed. Setup problems fail the same way
+    /// every time.
+    pub fn is_transient(self) -> bool {
+        matches!(
+            self,
+            TtsErrorKind::Timeout | TtsErrorKind::Crashed | TtsErrorKind::Other
+        )
+    }
+}
 
-    for path in &possible_paths {
-        if path.exists() {
-            return path.clone();
+impl TtsError {
+    pub fn new(kind: TtsErrorKind, message: impl Into<String>) -> Self {
+        TtsError {
+            kind,
+            message: message.into(),
         }
     }
+}
+
+/// Classify an engine failure from its stderr. Lines are checked for the
+/// most specific causes first, since a missing model or espeak data also
+/// makes ONNX Runtime complain; informational lines naming model paths
+/// aren't failures on their own.
+pub fn classify_engine_error(stderr: &str) -> TtsErrorKind {
+    let lines: Vec<String> = stderr.lines().map(str::to_lowercase).collect();
+    let any = |test: &dyn Fn(&str) -> bool| lines.iter().any(|line| test(line));
+    let failed = |line: &str| {
+        ["error", "failed", "exception", "no such file", "doesn't exist", "not found"]
+            .iter()
+            .any(|marker| line.contains(marker))
+    };
+
+    if any(&|l| l.contains("phontab") || (l.contains("espeak") && failed(l))) {
+        TtsErrorKind::MissingEspeakData
+    } else if any(&|l| l.contains("json.exception") || (l.contains("config") && failed(l))) {
+        TtsErrorKind::BadModelConfig
+    } else if any(&|l| (l.contains("model") || l.contains(".onnx")) && failed(l) && !l.contains("onnxruntime")) {
+        TtsErrorKind::MissingModel
+    } else if any(&|l| l.contains("onnxruntime") || l.contains("ort::exception")) {
+        TtsErrorKind::OnnxRuntime
+    } else {
+        TtsErrorKind::Other
+    }
+}
+
+/// Time allowed to synthesize `text` before the engine is considered hung
+pub fn synthesis_timeout(text: &str) -> Duration {
+    Duration::from_secs(15) + Duration::from_millis(50) * text.len() as u32
+}
+
+/// Speech engines that can synthesize audio
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackendKind {
+    /// Piper if installed, otherwise the first available engine
+    #[default]
+    Auto,
+    Piper,
+    Espeak,
+    /// Deterministic tones with exact timings, for automated tests
+    Mock,
+}
+
+impl BackendKind {
+    pub fn parse(value: &str) -> Option<BackendKind> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "auto" => Some(BackendKind::Auto),
+            "piper" => Some(BackendKind::Piper),
+            "espeak" | "espeak-ng" => Some(BackendKind::Espeak),
+            "mock" => Some(BackendKind::Mock),
+            _ => None,
+        }
+    }
+}
+
+/// A local text-to-speech engine
+pub trait TtsBackend: Send + Sync {
+    fn kind(&self) -> BackendKind;
+
+    /// Whether the engine is installed and can be run
+    fn is_available(&self) -> bool;
+
+    /// Voices this engine can speak with
+    fn list_voices(&self) -> Vec<VoiceInfo>;
+
+    /// Synthesize `text` into a WAV file at `output_path`
+    fn synthesize(
+        &self,
+        text: &str,
+        output_path: &str,
+        profile: &VoiceProfile,
+    ) -> Result<TtsResult, TtsError>;
+
+    /// Synthesize `text` straight into memory for streaming playback. The
+    /// default goes through a temporary WAV file.
+    fn synthesize_raw(&self, text: &str, profile: &VoiceProfile) -> Result<RawAudio, TtsError> {
+        with_temp_wav(|path| {
+            let result = self.synthesize(text, &path.to_string_lossy(), profile)?;
+            let (spec, samples) = read_temp_wav(path)?;
+            Ok(RawAudio {
+                sample_rate: spec.sample_rate,
+                channels: spec.channels,
+                samples,
+                word_timings: result.word_timings,
+            })
+        })
+    }
+
+    /// Abort synthesis in progress, killing the engine process if possible.
+    /// Later requests on this backend fail.
+    fn cancel(&self) {}
+
+    /// The executable and voice files `profile` resolves to, so cached
+    /// audio isn't reused once the installation changes
+    fn engine_identity(&self, _profile: &VoiceProfile) -> Result<String, TtsError> {
+        Ok(String::new())
+    }
+
+    /// Sample rate of the audio made for `profile`, if known before
+    /// synthesizing, so recordings from several voices can share one rate
+    fn sample_rate(&self, _profile: &VoiceProfile) -> Option<u32> {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackendInfo {
+    pub kind: BackendKind,
+    pub available: bool,
+}
+
+/// Engines tried, in order, when resolving `BackendKind::Auto`
+const AUTO_BACKENDS: [BackendKind; 2] = [BackendKind::Piper, BackendKind::Espeak];
+
+/// Create the backend for `kind`. `Auto` picks the first available engine,
+/// falling back to Piper so its setup errors are reported.
+pub fn create_backend(kind: BackendKind, config: &TtsConfig) -> Arc<dyn TtsBackend> {
+    match kind {
+        BackendKind::Piper => Arc::new(PiperBackend::new(config.clone())),
+        BackendKind::Espeak => Arc::new(EspeakBackend::new()),
+        BackendKind::Mock => Arc::new(MockBackend::new()),
+        BackendKind::Auto => AUTO_BACKENDS
+            .iter()
+            .map(|kind| create_backend(*kind, config))
+            .find(|backend| backend.is_available())
+            .unwrap_or_else(|| create_backend(BackendKind::Piper, config)),
+    }
+}
+
+/// Put an engine behind the synthesis cache, if there is one. SSML and
+/// dialogue are split into segments before they reach the cache.
+pub fn wrap_backend(
+    engine: Arc<dyn TtsBackend>,
+    cache: Option<Arc<SynthesisCache>>,
+    dialogue: DialogueVoices,
+) -> Arc<dyn TtsBackend> {
+    let mut backend = match cache {
+        Some(cache) => Arc::new(CachedBackend::new(engine, cache)),
+        None => engine,
+    };
+    if dialogue.enabled {
+        backend = Arc::new(DialogueBackend::new(backend, dialogue));
+    }
+    Arc::new(SsmlBackend::new(backend))
+}
+
+/// Report which engines are installed
+pub fn list_backends(config: &TtsConfig) -> Vec<BackendInfo> {
+    AUTO_BACKENDS
+        .iter()
+        .map(|kind| {
+            let backend = create_backend(*kind, config);
+            BackendInfo {
+                kind: backend.kind(),
+                available: backend.is_available(),
+            }
+        })
+        .collect()
+}
 
-    // Fallback - assume it's in PATH
-    PathBuf::from("piper")
+/// Run `f` with a fresh temporary WAV path, removing the file afterwards
+fn with_temp_wav<T>(f: impl FnOnce(&Path) -> Result<T, TtsError>) -> Result<T, TtsError> {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+    let path = std::env::temp_dir().join(format!(
+        "pdf_audiobook_raw_{}_{}.wav",
+        std::process::id(),
+        NEXT_ID.fetch_add(1, Ordering::Relaxed)
+    ));
+    let result = f(&path);
+    let _ = std::fs::remove_file(&path);
+    result
 }
 
-/// Get the default voice model path
-fn get_voice_model_path() -> PathBuf {
+fn read_temp_wav(path: &Path) -> Result<(hound::WavSpec, Vec<i16>), TtsError> {
+    read_wav_samples(path).map_err(|e| TtsError::new(TtsErrorKind::Io, e.message))
+}
+
+/// Read a child's pipe to the end on a helper thread
+fn drain<R: Read + Send + 'static>(pipe: Option<R>) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buffer = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buffer);
+        }
+        buffer
+    })
+}
+
+/// Run a speech engine, feeding `text` on stdin and failing on a non-zero exit.
+/// The engine is killed if it runs longer than `timeout` or `cancel` is
+/// cancelled. Returns whatever the engine wrote to stdout.
+pub(crate) fn run_with_stdin(
+    mut command: Command,
+    text: &str,
+    engine: &str,
+    timeout: Duration,
+    cancel: &CancelToken,
+) -> Result<Vec<u8>, TtsError> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| {
+            TtsError::new(TtsErrorKind::EngineNotFound, format!("Failed to start {}: {}", engine, e))
+        })?;
+
+    let stdout = drain(child.stdout.take());
+    let stderr = drain(child.stderr.take());
+    let stdin = child.stdin.take();
+    let child = Arc::new(Mutex::new(child));
+
+    let status = cancel.while_running(&child, || {
+        // Write text to stdin
+        if let Some(mut stdin) = stdin {
+            stdin.write_all(text.as_bytes()).map_err(|e| {
+                TtsError::new(TtsErrorKind::Crashed, format!("Failed to write to {} stdin: {}", engine, e))
+            })?;
+        }
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let mut child = child.lock().unwrap();
+            match child.try_wait() {
+                Ok(Some(status)) => return Ok(status),
+                Ok(None) if Instant::now() >= deadline => {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return Err(TtsError::new(
+                        TtsErrorKind::Timeout,
+                        format!("{} took longer than {} s", engine, timeout.as_secs()),
+                    ));
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    return Err(TtsError::new(
+                        TtsErrorKind::Crashed,
+                        format!("Failed to wait for {}: {}", engine, e),
+                    ))
+                }
+            }
+            drop(child);
+            thread::sleep(Duration::from_millis(20));
+        }
+    });
+    if cancel.is_cancelled() {
+        let _ = child.lock().unwrap().wait();
+        return Err(cancel.error());
+    }
+    let status = status?;
+
+    let stdout = stdout.join().unwrap_or_default();
+    let stderr = String::from_utf8_lossy(&stderr.join().unwrap_or_default()).to_string();
+    if !status.success() {
+        return Err(TtsError::new(
+            classify_engine_error(&stderr),
+            format!("{} failed: {}", engine, stderr),
+        ));
+    }
+
+    Ok(stdout)
+}
+
+/// Paths used to locate Piper and its voice models
+#[derive(Debug, Clone, Default)]
+pub struct TtsConfig {
+    pub piper_path: Option<PathBuf>,
+    pub voices_dir: Option<PathBuf>,
+}
+
+impl TtsConfig {
+    pub fn from_settings(settings: &AppSettings) -> Self {
+        TtsConfig {
+            piper_path: settings.effective_piper_path(),
+            voices_dir: settings.effective_voices_dir(),
+        }
+    }
+}
+
+/// Result of probing candidate locations for a file or directory
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PathProbe {
+    pub found: Option<String>,
+    pub tried: Vec<String>,
+}
+
+impl PathProbe {
+    fn search<I>(candidates: I, matches: fn(&Path) -> bool) -> Self
+    where
+        I: IntoIterator<Item = PathBuf>,
+    {
+        let mut tried = Vec::new();
+        for path in candidates {
+            tried.push(path.display().to_string());
+            if matches(&path) {
+                return PathProbe {
+                    found: Some(path.display().to_string()),
+                    tried,
+                };
+            }
+        }
+        PathProbe { found: None, tried }
+    }
+
+    fn path(&self) -> Option<PathBuf> {
+        self.found.as_ref().map(PathBuf::from)
+    }
+}
+
+/// Report on the Piper configuration, listing every path that was checked
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TtsConfigReport {
+    pub piper: PathProbe,
+    pub voices_dir: PathProbe,
+    pub default_voice: PathProbe,
+    pub ok: bool,
+}
+
+const PIPER_EXE: &str = if cfg!(windows) { "piper.exe" } else { "piper" };
+const DEFAULT_VOICE_MODEL: &str = "en_US-amy-medium.onnx";
+/// Sample rate of medium and high quality Piper voices
+const DEFAULT_SAMPLE_RATE: u32 = 22050;
+
+/// Default `piper` folders: next to the exe, the project root in dev mode, and the cwd
+fn default_piper_dirs() -> Vec<PathBuf> {
     let exe_dir = std::env::current_exe()
         .ok()
         .and_then(|p| p.parent().map(|p| p.to_path_buf()))
         .unwrap_or_else(|| PathBuf::from("."));
 
-    let possible_paths = vec![
+    vec![
         // Production: next to exe
-        exe_dir.join("piper").join("voices").join("en_US-amy-medium.onnx"),
+        exe_dir.join("piper"),
         // Dev mode: project root (exe is in src-tauri/target/debug)
-        exe_dir.join("..").join("..").join("..").join("piper").join("voices").join("en_US-amy-medium.onnx"),
-        exe_dir.join("..").join("..").join("..").join("..").join("piper").join("voices").join("en_US-amy-medium.onnx"),
+        exe_dir.join("..").join("..").join("..").join("piper"),
+        exe_dir.join("..").join("..").join("..").join("..").join("piper"),
         // Current working directory
-        PathBuf::from("piper").join("voices").join("en_US-amy-medium.onnx"),
-        // Absolute fallback for this specific project
-        PathBuf::from("F:\\Programming\\PdfAudio\\piper\\voices\\en_US-amy-medium.onnx"),
-    ];
+        PathBuf::from("piper"),
+    ]
+}
+
+/// Locate the Piper executable. A configured path is authoritative; otherwise
+/// the default folders are searched before falling back to `PATH`.
+fn find_piper(config: &TtsConfig) -> PathProbe {
+    let candidates: Vec<PathBuf> = match &config.piper_path {
+        Some(path) if path.components().count() == 1 => {
+            // A bare name such as "piper" is looked up on PATH
+            vec![which::which(path).unwrap_or_else(|_| path.clone())]
+        }
+        Some(path) => vec![path.clone()],
+        None => {
+            let mut paths: Vec<PathBuf> = default_piper_dirs()
+                .into_iter()
+                .map(|dir| dir.join(PIPER_EXE))
+                .collect();
+            if let Ok(on_path) = which::which("piper") {
+                paths.push(on_path);
+            }
+            paths
+        }
+    };
+
+    PathProbe::search(candidates, |p| p.is_file())
+}
+
+/// Locate the voices directory
+fn find_voices_dir(config: &TtsConfig) -> PathProbe {
+    let candidates = match &config.voices_dir {
+        Some(dir) => vec![dir.clone()],
+        None => default_piper_dirs()
+            .into_iter()
+            .map(|dir| dir.join("voices"))
+            .collect(),
+    };
+
+    PathProbe::search(candidates, |p| p.is_dir())
+}
+
+/// Locate the default voice model inside the voices directory
+fn find_default_voice(voices_dir: &PathProbe) -> PathProbe {
+    let candidates = voices_dir.path().map(|dir| dir.join(DEFAULT_VOICE_MODEL));
+    PathProbe::search(candidates, |p| p.is_file())
+}
+
+/// Check the configuration and report which paths were tried
+pub fn validate_config(config: &TtsConfig) -> TtsConfigReport {
+    let piper = find_piper(config);
+    let voices_dir = find_voices_dir(config);
+    let default_voice = find_default_voice(&voices_dir);
+    let ok = piper.found.is_some() && default_voice.found.is_some();
+
+    TtsConfigReport {
+        piper,
+        voices_dir,
+        default_voice,
+        ok,
+    }
+}
 
-    for path in &possible_paths {
-        if path.exists() {
-            return path.clone();
+/// Voice choice and Piper synthesis parameters. Unset fields fall back to
+/// the persisted default profile, then to the voice model's own defaults.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VoiceProfile {
+    pub voice_id: Option<String>,
+    /// Speaker for multi-speaker models
+    pub speaker_id: Option<u32>,
+    /// Phoneme length; larger is slower speech
+    pub length_scale: Option<f32>,
+    /// Generator noise
+    pub noise_scale: Option<f32>,
+    /// Phoneme width noise
+    pub noise_w: Option<f32>,
+    /// Seconds of silence after each sentence
+    pub sentence_silence: Option<f32>,
+}
+
+impl VoiceProfile {
+    /// Fill unset fields from `defaults`. The default speaker belongs to
+    /// the default voice, so it isn't inherited by another voice.
+    pub fn or(&self, defaults: &VoiceProfile) -> VoiceProfile {
+        let same_voice = self.voice_id.is_none() || self.voice_id == defaults.voice_id;
+        VoiceProfile {
+            voice_id: self.voice_id.clone().or_else(|| defaults.voice_id.clone()),
+            speaker_id: self
+                .speaker_id
+                .or(defaults.speaker_id.filter(|_| same_voice)),
+            length_scale: self.length_scale.or(defaults.length_scale),
+            noise_scale: self.noise_scale.or(defaults.noise_scale),
+            noise_w: self.noise_w.or(defaults.noise_w),
+            sentence_silence: self.sentence_silence.or(defaults.sentence_silence),
+        }
+    }
+
+    fn validate(&self) -> Result<(), TtsError> {
+        let checks = [
+            ("length_scale", self.length_scale, 0.1, 5.0),
+            ("noise_scale", self.noise_scale, 0.0, 2.0),
+            ("noise_w", self.noise_w, 0.0, 2.0),
+            ("sentence_silence", self.sentence_silence, 0.0, 10.0),
+        ];
+        for (name, value, min, max) in checks {
+            if let Some(v) = value {
+                if !(min..=max).contains(&v) {
+                    return Err(TtsError::new(
+                        TtsErrorKind::InvalidProfile,
+                        format!("{} must be between {} and {}, got {}", name, min, max, v),
+                    ));
+                }
+            }
         }
+        Ok(())
     }
 
-    // Fallback
-    PathBuf::from("piper/voices/en_US-amy-medium.onnx")
+    /// Piper command line flags for the synthesis parameters. The speaker is
+    /// sent with each request instead, so one process serves every speaker.
+    fn piper_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        let scales = [
+            ("--length_scale", self.length_scale),
+            ("--noise_scale", self.noise_scale),
+            ("--noise_w", self.noise_w),
+            ("--sentence_silence", self.sentence_silence),
+        ];
+        for (flag, value) in scales {
+            if let Some(v) = value {
+                args.extend([flag.to_string(), v.to_string()]);
+            }
+        }
+        args
+    }
+}
+
+/// Resolve the voice model for a profile. Without a voice id the default
+/// model is used, or the first installed voice if that is missing.
+fn resolve_voice(config: &TtsConfig, profile: &VoiceProfile) -> Result<VoiceInfo, TtsError> {
+    let voices_dir = find_voices_dir(config);
+    let dir = voices_dir.path().ok_or_else(|| {
+        TtsError::new(
+            TtsErrorKind::MissingModel,
+            format!("Voices directory not found. Looked in: {}", voices_dir.tried.join(", ")),
+        )
+    })?;
+    let voices: Vec<VoiceInfo> = scan_voices_dir(&dir)
+        .into_iter()
+        .filter(|v| v.available)
+        .collect();
+
+    let voice = match &profile.voice_id {
+        Some(id) => voices.into_iter().find(|v| &v.id == id).ok_or_else(|| {
+            TtsError::new(
+                TtsErrorKind::MissingModel,
+                format!("Voice '{}' is not installed in {}", id, dir.display()),
+            )
+        })?,
+        None => {
+            let default_id = DEFAULT_VOICE_MODEL.trim_end_matches(".onnx");
+            let default = voices.iter().position(|v| v.id == default_id).unwrap_or(0);
+            voices.into_iter().nth(default).ok_or_else(|| {
+                TtsError::new(
+                    TtsErrorKind::MissingModel,
+                    format!("No voice models found in {}", dir.display()),
+                )
+            })?
+        }
+    };
+
+    if let Some(speaker) = profile.speaker_id {
+        if voice.speakers.is_empty() && speaker != 0 {
+            return Err(TtsError::new(
+                TtsErrorKind::InvalidProfile,
+                format!("Voice '{}' has a single speaker", voice.id),
+            ));
+        }
+        if !voice.speakers.is_empty() && !voice.speakers.iter().any(|s| s.id == speaker) {
+            return Err(TtsError::new(
+                TtsErrorKind::InvalidProfile,
+                format!("Voice '{}' has no speaker {}", voice.id, speaker),
+            ));
+        }
+    }
+
+    Ok(voice)
 }
 
 /// Estimate word timings based on text and speech rate
@@ -94,7 +643,7 @@ pub fn estimate_word_timings(text: &str, speed: f32) -> Vec<WordTiming> {
     
     for word in words {
         // Adjust timing based on word length
-        let word_length_factor = (word.len() as f32 / 5.0).max(0.5).min(2.0);
+        let word_length_factor = (word.len() as f32 / 5.0).clamp(0.5, 2.0);
         let duration = (ms_per_word as f32 * word_length_factor) as u64;
         
         timings.push(WordTiming {
@@ -109,56 +658,43 @@ pub fn estimate_word_timings(text: &str, speed: f32) -> Vec<WordTiming> {
     timings
 }
 
-/// Generate audio from text using Piper TTS
-pub fn generate_audio(text: &str, output_path: &str) -> Result<TtsResult, TtsError> {
-    let piper_path = get_piper_path();
-    let model_path = get_voice_model_path();
-    
-    // Check if Piper exists
-    if !piper_path.exists() && which::which("piper").is_err() {
-        return Err(TtsError {
-            message: format!(
-                "Piper TTS not found. Please download it from https://github.com/rhasspy/piper/releases and place it in the 'piper' folder. Looking for: {}",
-                piper_path.display()
+/// Identify the Piper worker that can serve a profile, and the voice it speaks
+fn piper_worker_key(config: &TtsConfig, profile: &VoiceProfile) -> Result<(WorkerKey, VoiceInfo), TtsError> {
+    let piper = find_piper(config);
+    let piper_path = piper.path().ok_or_else(|| {
+        TtsError::new(
+            TtsErrorKind::EngineNotFound,
+            format!(
+                "Piper TTS not found. Please download it from https://github.com/rhasspy/piper/releases and set its location in settings or the {} environment variable. Looked in: {}",
+                PIPER_PATH_ENV,
+                piper.tried.join(", ")
             ),
-        });
-    }
-
-    // Run Piper to generate audio
-    let mut child = Command::new(&piper_path)
-        .args([
-            "--model", model_path.to_str().unwrap_or(""),
-            "--output_file", output_path,
-        ])
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .map_err(|e| TtsError {
-            message: format!("Failed to start Piper: {}", e),
-        })?;
+        )
+    })?;
 
-    // Write text to stdin
-    if let Some(mut stdin) = child.stdin.take() {
-        stdin.write_all(text.as_bytes()).map_err(|e| TtsError {
-            message: format!("Failed to write to Piper stdin: {}", e),
-        })?;
-    }
+    profile.validate()?;
+    let voice = resolve_voice(config, profile)?;
 
-    let output = child.wait_with_output().map_err(|e| TtsError {
-        message: format!("Failed to wait for Piper: {}", e),
-    })?;
+    let key = WorkerKey {
+        program: piper_path,
+        model: voice.model_path.clone(),
+        args: profile.piper_args(),
+    };
+    Ok((key, voice))
+}
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(TtsError {
-            message: format!("Piper failed: {}", stderr),
-        });
-    }
+/// Generate audio from text using a persistent Piper worker
+pub fn generate_audio(
+    text: &str,
+    output_path: &str,
+    config: &TtsConfig,
+    profile: &VoiceProfile,
+    cancel: &CancelToken,
+) -> Result<TtsResult, TtsError> {
+    let (key, _) = piper_worker_key(config, profile)?;
+    WorkerPool::global().synthesize(&key, text, Path::new(output_path), profile.speaker_id, cancel)?;
 
-    // Estimate word timings
-    let word_timings = estimate_word_timings(text, 1.0);
-    let duration_ms = word_timings.last().map(|w| w.end_ms).unwrap_or(0);
+    let (word_timings, duration_ms) = align_wav(text, Path::new(output_path))?;
 
     Ok(TtsResult {
         audio_path: output_path.to_string(),
@@ -167,26 +703,365 @@ pub fn generate_audio(text: &str, output_path: &str) -> Result<TtsResult, TtsErr
     })
 }
 
+/// Generate audio into memory on a persistent Piper worker, so streamed
+/// chunks don't wait for a model to load. Piper can only answer a request
+/// with a file, which is read back once and aligned in memory.
+pub fn generate_raw_audio(
+    text: &str,
+    config: &TtsConfig,
+    profile: &VoiceProfile,
+    cancel: &CancelToken,
+) -> Result<RawAudio, TtsError> {
+    let (key, _) = piper_worker_key(config, profile)?;
+    with_temp_wav(|path| {
+        WorkerPool::global().synthesize(&key, text, path, profile.speaker_id, cancel)?;
+        let (spec, samples) = read_temp_wav(path)?;
+        Ok(RawAudio {
+            sample_rate: spec.sample_rate,
+            channels: spec.channels,
+            word_timings: align_words(text, &samples, spec.channels, spec.sample_rate),
+            samples,
+        })
+    })
+}
+
 /// Check if Piper TTS is available
-pub fn is_piper_available() -> bool {
-    let piper_path = get_piper_path();
-    piper_path.exists() || which::which("piper").is_ok()
+pub fn is_piper_available(config: &TtsConfig) -> bool {
+    find_piper(config).found.is_some()
+}
+
+/// Piper neural TTS, served by long-lived worker processes
+pub struct PiperBackend {
+    config: TtsConfig,
+    cancel: CancelToken,
+}
+
+impl PiperBackend {
+    pub fn new(config: TtsConfig) -> Self {
+        PiperBackend {
+            config,
+            cancel: CancelToken::new(),
+        }
+    }
+}
+
+impl TtsBackend for PiperBackend {
+    fn kind(&self) -> BackendKind {
+        BackendKind::Piper
+    }
+
+    fn is_available(&self) -> bool {
+        is_piper_available(&self.config)
+    }
+
+    fn list_voices(&self) -> Vec<VoiceInfo> {
+        get_available_voices(&self.config)
+    }
+
+    fn synthesize(
+        &self,
+        text: &str,
+        output_path: &str,
+        profile: &VoiceProfile,
+    ) -> Result<TtsResult, TtsError> {
+        generate_audio(text, output_path, &self.config, profile, &self.cancel)
+    }
+
+    fn synthesize_raw(&self, text: &str, profile: &VoiceProfile) -> Result<RawAudio, TtsError> {
+        generate_raw_audio(text, &self.config, profile, &self.cancel)
+    }
+
+    fn engine_identity(&self, profile: &VoiceProfile) -> Result<String, TtsError> {
+        let (key, _) = piper_worker_key(&self.config, profile)?;
+        // A model replaced in place keeps its path
+        let modified = std::fs::metadata(&key.model)
+            .and_then(|meta| meta.modified())
+            .ok()
+            .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|age| age.as_secs())
+            .unwrap_or_default();
+        Ok(format!("{}|{}|{}", key.program.display(), key.model, modified))
+    }
+
+    fn sample_rate(&self, profile: &VoiceProfile) -> Option<u32> {
+        piper_worker_key(&self.config, profile)
+            .ok()
+            .map(|(_, voice)| voice.sample_rate.unwrap_or(DEFAULT_SAMPLE_RATE))
+    }
+
+    fn cancel(&self) {
+        self.cancel.cancel();
+    }
+}
+
+/// A speaker within a multi-speaker voice model
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpeakerInfo {
+    pub id: u32,
+    pub name: String,
 }
 
 /// Get voice model info
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VoiceInfo {
+    /// Model file name without the `.onnx` extension, e.g. `en_US-amy-medium`
+    pub id: String,
     pub name: String,
     pub language: String,
+    pub quality: Option<String>,
+    pub sample_rate: Option<u32>,
+    pub speakers: Vec<SpeakerInfo>,
+    pub model_path: String,
+    /// False when the companion `.onnx.json` config is missing or unreadable
     pub available: bool,
 }
 
-pub fn get_available_voices() -> Vec<VoiceInfo> {
-    let model_path = get_voice_model_path();
-    
-    vec![VoiceInfo {
-        name: "Amy (US English)".to_string(),
-        language: "en-US".to_string(),
-        available: model_path.exists(),
-    }]
+/// The subset of Piper's `.onnx.json` voice config that we use
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct VoiceConfig {
+    audio: VoiceAudioConfig,
+    language: VoiceLanguageConfig,
+    dataset: Option<String>,
+    num_speakers: u32,
+    speaker_id_map: HashMap<String, u32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct VoiceAudioConfig {
+    sample_rate: Option<u32>,
+    quality: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct VoiceLanguageConfig {
+    code: Option<String>,
+    name_english: Option<String>,
+    country_english: Option<String>,
+}
+
+/// Build a `VoiceInfo` from a model path and the contents of its config, if any
+fn voice_info(model_path: &Path, config_json: Option<&str>) -> VoiceInfo {
+    let id = model_path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let config: Option<VoiceConfig> = config_json.and_then(|json| serde_json::from_str(json).ok());
+    let available = config.is_some();
+    let config = config.unwrap_or_default();
+
+    // Piper ids look like `<lang>_<REGION>-<dataset>-<quality>`
+    let mut parts = id.splitn(3, '-');
+    let id_language = parts.next().unwrap_or_default().to_string();
+    let id_dataset = parts.next().map(|s| s.to_string());
+    let id_quality = parts.next().map(|s| s.to_string());
+
+    let language = config.language.code.unwrap_or(id_language).replace('_', "-");
+    let dataset = config.dataset.or(id_dataset).unwrap_or_else(|| id.clone());
+    let mut name = capitalize(&dataset.replace('_', " "));
+    match (config.language.name_english, config.language.country_english) {
+        (Some(lang), Some(country)) => name = format!("{} ({}, {})", name, lang, country),
+        (Some(lang), None) => name = format!("{} ({})", name, lang),
+        _ if !language.is_empty() => name = format!("{} ({})", name, language),
+        _ => {}
+    }
+
+    let mut speakers: Vec<SpeakerInfo> = config
+        .speaker_id_map
+        .into_iter()
+        .map(|(name, id)| SpeakerInfo { id, name })
+        .collect();
+    speakers.sort_by_key(|s| s.id);
+    if speakers.is_empty() && config.num_speakers > 1 {
+        speakers = (0..config.num_speakers)
+            .map(|id| SpeakerInfo {
+                id,
+                name: format!("Speaker {}", id),
+            })
+            .collect();
+    }
+
+    VoiceInfo {
+        id,
+        name,
+        language,
+        quality: config.audio.quality.or(id_quality),
+        sample_rate: config.audio.sample_rate,
+        speakers,
+        model_path: model_path.display().to_string(),
+        available,
+    }
+}
+
+fn capitalize(s: &str) -> String {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Scan a directory for `*.onnx` models and read their `.onnx.json` configs
+fn scan_voices_dir(dir: &Path) -> Vec<VoiceInfo> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut voices: Vec<VoiceInfo> = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "onnx"))
+        .map(|model_path| {
+            let mut config_path = model_path.clone().into_os_string();
+            config_path.push(".json");
+            let config_json = std::fs::read_to_string(config_path).ok();
+            voice_info(&model_path, config_json.as_deref())
+        })
+        .collect();
+
+    voices.sort_by(|a, b| a.id.cmp(&b.id));
+    voices
+}
+
+/// List every voice model installed in the voices directory
+pub fn get_available_voices(config: &TtsConfig) -> Vec<VoiceInfo> {
+    find_voices_dir(config)
+        .path()
+        .map(|dir| scan_voices_dir(&dir))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AMY_CONFIG: &str = r#"{
+        "audio": { "sample_rate": 22050, "quality": "medium" },
+        "language": { "code": "en_US", "name_english": "English", "country_english": "United States" },
+        "dataset": "amy",
+        "num_speakers": 1,
+        "speaker_id_map": {}
+    }"#;
+
+    #[test]
+    fn test_voice_info_from_config() {
+        let voice = voice_info(Path::new("voices/en_US-amy-medium.onnx"), Some(AMY_CONFIG));
+        assert_eq!(voice.id, "en_US-amy-medium");
+        assert_eq!(voice.name, "Amy (English, United States)");
+        assert_eq!(voice.language, "en-US");
+        assert_eq!(voice.quality.as_deref(), Some("medium"));
+        assert_eq!(voice.sample_rate, Some(22050));
+        assert!(voice.speakers.is_empty());
+        assert!(voice.available);
+    }
+
+    #[test]
+    fn test_voice_info_multi_speaker() {
+        let config = r#"{ "num_speakers": 2, "speaker_id_map": { "p226": 1, "p225": 0 } }"#;
+        let voice = voice_info(Path::new("en_GB-vctk-medium.onnx"), Some(config));
+        let names: Vec<&str> = voice.speakers.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["p225", "p226"]);
+    }
+
+    #[test]
+    fn test_voice_info_without_config() {
+        let voice = voice_info(Path::new("de_DE-thorsten-high.onnx"), None);
+        assert_eq!(voice.name, "Thorsten (de-DE)");
+        assert_eq!(voice.quality.as_deref(), Some("high"));
+        assert!(!voice.available);
+    }
+
+    #[test]
+    fn test_voice_profile_overrides_defaults() {
+        let defaults = VoiceProfile {
+            voice_id: Some("en_US-amy-medium".to_string()),
+            length_scale: Some(1.2),
+            noise_w: Some(0.8),
+            ..Default::default()
+        };
+        let profile = VoiceProfile {
+            speaker_id: Some(3),
+            length_scale: Some(0.9),
+            ..Default::default()
+        }
+        .or(&defaults);
+
+        assert_eq!(profile.voice_id.as_deref(), Some("en_US-amy-medium"));
+        assert_eq!(
+            profile.piper_args(),
+            vec!["--length_scale", "0.9", "--noise_w", "0.8"]
+        );
+
+        // A speaker of the default voice doesn't carry over to another one
+        let defaults = VoiceProfile {
+            speaker_id: Some(3),
+            ..defaults
+        };
+        let other = VoiceProfile {
+            voice_id: Some("en_GB-vctk-medium".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(other.or(&defaults).speaker_id, None);
+        assert_eq!(VoiceProfile::default().or(&defaults).speaker_id, Some(3));
+    }
+
+    #[test]
+    fn test_voice_profile_rejects_out_of_range() {
+        let profile = VoiceProfile {
+            length_scale: Some(0.0),
+            ..Default::default()
+        };
+        assert!(profile.validate().is_err());
+    }
+
+    #[test]
+    fn test_classify_piper_failures() {
+        let cases = [
+            (
+                "[piper] [info] Loading voice from /v/en_US-amy-medium.onnx (config=/v/en_US-amy-medium.onnx.json)\n\
+                 Error processing file '/usr/share/espeak-ng-data/phontab': No such file or directory.",
+                TtsErrorKind::MissingEspeakData,
+            ),
+            (
+                "[error] Model config doesn't exist: /v/en_US-amy-medium.onnx.json",
+                TtsErrorKind::BadModelConfig,
+            ),
+            (
+                "terminate called after throwing an instance of 'nlohmann::json_abi_v3_11_2::detail::parse_error'\n\
+                 what():  [json.exception.parse_error.101] parse error at line 1",
+                TtsErrorKind::BadModelConfig,
+            ),
+            ("[error] Model doesn't exist: /v/missing.onnx", TtsErrorKind::MissingModel),
+            (
+                "[piper] [info] Loading voice from /v/en_US-amy-medium.onnx\n\
+                 terminate called after throwing an instance of 'Ort::Exception'\n\
+                 what():  Load model from /v/en_US-amy-medium.onnx failed: onnxruntime protobuf parsing failed",
+                TtsErrorKind::OnnxRuntime,
+            ),
+            ("[piper] [info] Loading voice from /v/en_US-amy-medium.onnx", TtsErrorKind::Other),
+        ];
+        for (stderr, kind) in cases {
+            assert_eq!(classify_engine_error(stderr), kind, "{}", stderr);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_cancel_kills_engine() {
+        let cancel = CancelToken::new();
+        let canceller = cancel.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            canceller.cancel();
+        });
+        let mut command = Command::new("sh");
+        command.args(["-c", "exec sleep 30"]);
+        let started = Instant::now();
+        let result = run_with_stdin(command, "Hello", "sleep", Duration::from_secs(60), &cancel);
+
+        assert_eq!(result.unwrap_err().kind, TtsErrorKind::Cancelled);
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
 }
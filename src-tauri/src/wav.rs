@@ -0,0 +1,172 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WavError {
+    pub message: String,
+}
+
+impl From<hound::Error> for WavError {
+    fn from(err: hound::Error) -> Self {
+        WavError {
+            message: format!("WAV error: {}", err),
+        }
+    }
+}
+
+/// Number of samples covering `ms` milliseconds at `sample_rate`
+pub fn ms_to_samples(ms: u64, sample_rate: u32) -> usize {
+    (ms * sample_rate as u64 / 1000) as usize
+}
+
+/// Read a 16-bit PCM WAV file into memory
+pub fn read_wav_samples(path: &Path) -> Result<(hound::WavSpec, Vec<i16>), WavError> {
+    let mut reader = hound::WavReader::open(path)?;
+    let spec = reader.spec();
+    if spec.sample_format != hound::SampleFormat::Int || spec.bits_per_sample != 16 {
+        return Err(WavError {
+            message: format!("{} is not 16-bit PCM", path.display()),
+        });
+    }
+    let samples = reader.samples::<i16>().collect::<Result<Vec<_>, _>>()?;
+    Ok((spec, samples))
+}
+
+/// Convert mono samples between sample rates by linear interpolation
+pub fn resample(samples: &[i16], from_rate: u32, to_rate: u32) -> Vec<i16> {
+    if from_rate == to_rate || samples.is_empty() {
+        return samples.to_vec();
+    }
+    let length = (samples.len() as u64 * to_rate as u64 / from_rate as u64) as usize;
+    let step = from_rate as f64 / to_rate as f64;
+    (0..length)
+        .map(|i| {
+            let position = i as f64 * step;
+            let index = position as usize;
+            let next = samples[(index + 1).min(samples.len() - 1)] as f64;
+            let current = samples[index.min(samples.len() - 1)] as f64;
+            (current + (next - current) * position.fract()).round() as i16
+        })
+        .collect()
+}
+
+/// Rewrite a mono WAV file at `sample_rate` if it was made at another rate
+pub fn resample_wav(path: &Path, sample_rate: u32) -> Result<(), WavError> {
+    let (spec, samples) = read_wav_samples(path)?;
+    if spec.sample_rate == sample_rate {
+        return Ok(());
+    }
+    if spec.channels != 1 {
+        return Err(WavError {
+            message: format!("{} is not mono", path.display()),
+        });
+    }
+    write_wav(path, sample_rate, &resample(&samples, spec.sample_rate, sample_rate))
+}
+
+/// Write mono 16-bit PCM samples to a WAV file
+pub fn write_wav(path: &Path, sample_rate: u32, samples: &[i16]) -> Result<(), WavError> {
+    write_wav_channels(path, 1, sample_rate, samples)
+}
+
+/// Write interleaved 16-bit PCM samples to a WAV file
+pub fn write_wav_channels(path: &Path, channels: u16, sample_rate: u32, samples: &[i16]) -> Result<(), WavError> {
+    let spec = hound::WavSpec {
+        channels,
+        sample_rate,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut writer = hound::WavWriter::create(path, spec)?;
+    for &sample in samples {
+        writer.write_sample(sample)?;
+    }
+    writer.finalize()?;
+    Ok(())
+}
+
+/// Sample rate and per-input lengths of a concatenated file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConcatLayout {
+    pub sample_rate: u32,
+    /// Frames (samples per channel) contributed by each input
+    pub frames: Vec<u64>,
+    /// Frames of silence inserted before each input
+    pub gap_frames: Vec<u64>,
+}
+
+impl ConcatLayout {
+    /// Start of each input and the total length, in milliseconds. Offsets are
+    /// computed from cumulative frames so rounding doesn't accumulate.
+    pub fn offsets_ms(&self) -> (Vec<u64>, u64) {
+        let mut offsets = Vec::with_capacity(self.frames.len());
+        let mut total = 0u64;
+        for (frames, gap) in self.frames.iter().zip(&self.gap_frames) {
+            total += gap;
+            offsets.push(frames_to_ms(total, self.sample_rate));
+            total += frames;
+        }
+        (offsets, frames_to_ms(total, self.sample_rate))
+    }
+}
+
+pub fn frames_to_ms(frames: u64, sample_rate: u32) -> u64 {
+    if sample_rate == 0 {
+        return 0;
+    }
+    frames * 1000 / sample_rate as u64
+}
+
+/// Concatenate 16-bit PCM WAV files with identical formats into `output`,
+/// inserting `gaps_ms[i]` of silence before input `i`
+pub fn concat_wavs(inputs: &[PathBuf], gaps_ms: &[u64], output: &Path) -> Result<ConcatLayout, WavError> {
+    let mut writer: Option<hound::WavWriter<_>> = None;
+    let mut first_spec: Option<hound::WavSpec> = None;
+    let mut frames = Vec::with_capacity(inputs.len());
+    let mut gap_frames = Vec::with_capacity(inputs.len());
+
+    for (index, input) in inputs.iter().enumerate() {
+        let mut reader = hound::WavReader::open(input)?;
+        let spec = reader.spec();
+        if spec.sample_format != hound::SampleFormat::Int || spec.bits_per_sample != 16 {
+            return Err(WavError {
+                message: format!("{} is not 16-bit PCM", input.display()),
+            });
+        }
+        match first_spec {
+            Some(first) if first != spec => {
+                return Err(WavError {
+                    message: format!("{} has a different format than the first file", input.display()),
+                });
+            }
+            Some(_) => {}
+            None => {
+                first_spec = Some(spec);
+                writer = Some(hound::WavWriter::create(output, spec)?);
+            }
+        }
+
+        let gap = ms_to_samples(gaps_ms.get(index).copied().unwrap_or(0), spec.sample_rate);
+        frames.push(reader.duration() as u64);
+        gap_frames.push(gap as u64);
+        if let Some(writer) = writer.as_mut() {
+            for _ in 0..gap * spec.channels as usize {
+                writer.write_sample(0i16)?;
+            }
+            for sample in reader.samples::<i16>() {
+                writer.write_sample(sample?)?;
+            }
+        }
+    }
+
+    let sample_rate = first_spec.map(|spec| spec.sample_rate).unwrap_or(22050);
+    match writer {
+        Some(writer) => writer.finalize()?,
+        None => write_wav(output, sample_rate, &[])?,
+    }
+    Ok(ConcatLayout {
+        sample_rate,
+        frames,
+        gap_frames,
+    })
+}
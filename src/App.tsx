@@ -1,8 +1,9 @@
 import { useState, useEffect, useCallback, useRef } from 'react';
 import { invoke } from '@tauri-apps/api/core';
+import { listen } from '@tauri-apps/api/event';
 import { PdfUploader } from './components/PdfUploader';
 import { Teleprompter } from './components/Teleprompter';
-import { AudioControls } from './components/AudioControls';
+import { AudioControls, OutputDevice, SkipUnit } from './components/AudioControls';
 import './App.css';
 
 interface WordTiming {
@@ -11,20 +12,46 @@ interface WordTiming {
   end_ms: number;
 }
 
-interface TtsResult {
+interface SynthesisManifest {
   audio_path: string;
   word_timings: WordTiming[];
   duration_ms: number;
 }
 
+interface SynthesisProgress {
+  completed: number;
+  total: number;
+  current_chunk: number;
+  percent: number;
+  elapsed_ms: number;
+  eta_ms: number;
+}
+
+type JobEvent =
+  | ({ type: 'progress'; job_id: number } & SynthesisProgress)
+  | { type: 'finished'; job_id: number; manifest: SynthesisManifest }
+  | { type: 'failed'; job_id: number; error: string; kind: string | null }
+  | { type: 'cancelled'; job_id: number };
+
 interface AudioState {
   is_playing: boolean;
   position_ms: number;
   duration_ms: number;
   speed: number;
   volume: number;
+  segment_index: number | null;
+  segment_count: number;
+  last_error: { kind: string; message: string } | null;
+  output_device: string | null;
 }
 
+type PlaybackEvent =
+  | ({ type: 'state' } & AudioState)
+  | { type: 'position'; position_ms: number }
+  | { type: 'word'; index: number | null }
+  | { type: 'segment'; index: number }
+  | { type: 'ended' };
+
 function App() {
   // PDF State
   const [paragraphs, setParagraphs] = useState<string[]>([]);
@@ -33,9 +60,14 @@ function App() {
   // Audio State
   const [hasAudio, setHasAudio] = useState(false);
   const [isPreparing, setIsPreparing] = useState(false);
+  const [progress, setProgress] = useState<SynthesisProgress | null>(null);
   const [isPlaying, setIsPlaying] = useState(false);
   const [positionMs, setPositionMs] = useState(0);
   const [durationMs, setDurationMs] = useState(0);
+  const [wordIndex, setWordIndex] = useState<number | null>(null);
+  const [outputDevices, setOutputDevices] = useState<OutputDevice[]>([]);
+  const [outputDevice, setOutputDevice] = useState<string | null>(null);
+  const outputInUseRef = useRef<string | null>(null);
   const [speed, setSpeed] = useState(1.0);
   const [volume, setVolume] = useState(1.0);
   const [wordTimings, setWordTimings] = useState<WordTiming[]>([]);
@@ -44,7 +76,9 @@ function App() {
   // TTS availability
   const [ttsAvailable, setTtsAvailable] = useState<boolean | null>(null);
   
-  const positionIntervalRef = useRef<number | null>(null);
+  const jobIdRef = useRef<number | null>(null);
+  // Events that arrived before `prepare_audio` returned their job id
+  const earlyJobEventsRef = useRef<Map<number, JobEvent>>(new Map());
 
   // Check TTS availability on mount
   useEffect(() => {
@@ -60,40 +94,97 @@ function App() {
     checkTts();
   }, []);
 
-  // Poll for audio position while playing
+  // Follow the background synthesis job
+  const applyJobEvent = (payload: JobEvent) => {
+    switch (payload.type) {
+      case 'progress':
+        setProgress(payload);
+        return;
+      case 'finished':
+        setWordTimings(payload.manifest.word_timings);
+        setDurationMs(payload.manifest.duration_ms);
+        setHasAudio(true);
+        break;
+      case 'failed':
+        setError(`Failed to prepare audio: ${payload.error}`);
+        console.error('TTS Error:', payload.error);
+        break;
+    }
+    jobIdRef.current = null;
+    setIsPreparing(false);
+    setProgress(null);
+  };
+
   useEffect(() => {
-    if (isPlaying) {
-      positionIntervalRef.current = window.setInterval(async () => {
-        try {
-          const state = await invoke<AudioState>('get_audio_state');
-          setPositionMs(state.position_ms);
-          setIsPlaying(state.is_playing);
-          
-          // Check if finished
-          const finished = await invoke<boolean>('is_audio_finished');
-          if (finished) {
-            setIsPlaying(false);
-          }
-        } catch (err) {
-          console.error('Failed to get audio state:', err);
-        }
-      }, 50); // Update every 50ms for smooth tracking
-    } else {
-      if (positionIntervalRef.current) {
-        clearInterval(positionIntervalRef.current);
-        positionIntervalRef.current = null;
+    const unlisten = listen<JobEvent>('synthesis-job', ({ payload }) => {
+      if (payload.job_id === jobIdRef.current) {
+        applyJobEvent(payload);
+      } else if (jobIdRef.current === null && payload.type !== 'progress') {
+        earlyJobEventsRef.current.set(payload.job_id, payload);
       }
+    });
+    return () => {
+      unlisten.then((stop) => stop());
+    };
+  }, []);
+
+  const loadOutputDevices = async () => {
+    try {
+      setOutputDevices(await invoke<OutputDevice[]>('get_output_devices'));
+    } catch (err) {
+      console.error('Failed to list output devices:', err);
     }
+  };
 
-    return () => {
-      if (positionIntervalRef.current) {
-        clearInterval(positionIntervalRef.current);
+  useEffect(() => {
+    loadOutputDevices();
+    invoke<{ output_device: string | null }>('get_settings')
+      .then((settings) => setOutputDevice(settings.output_device))
+      .catch((err) => console.error('Failed to load settings:', err));
+  }, []);
+
+  // Playback state is pushed from the audio thread
+  useEffect(() => {
+    const unlisten = listen<PlaybackEvent>('playback', ({ payload }) => {
+      switch (payload.type) {
+        case 'state':
+          setIsPlaying(payload.is_playing);
+          setPositionMs(payload.position_ms);
+          setDurationMs(payload.duration_ms);
+          // A device was plugged in or removed
+          if (payload.output_device !== outputInUseRef.current) {
+            outputInUseRef.current = payload.output_device;
+            loadOutputDevices();
+          }
+          if (payload.last_error) {
+            setError(`Playback error: ${payload.last_error.message}`);
+          }
+          break;
+        case 'position':
+          setPositionMs(payload.position_ms);
+          break;
+        case 'word':
+          setWordIndex(payload.index);
+          break;
+        case 'ended':
+          setIsPlaying(false);
+          break;
       }
+    });
+    return () => {
+      unlisten.then((stop) => stop());
     };
-  }, [isPlaying]);
+  }, []);
+
+  const cancelPreparing = async () => {
+    if (jobIdRef.current !== null) {
+      await invoke('cancel_job', { jobId: jobIdRef.current });
+    }
+  };
 
   // Clear PDF and reset state
   const handleClear = async () => {
+    await cancelPreparing();
     try {
       await invoke('stop_audio');
     } catch (e) {
@@ -104,6 +195,7 @@ function App() {
     setWordTimings([]);
     setPositionMs(0);
     setDurationMs(0);
+    setWordIndex(null);
     setIsPlaying(false);
     setError(null);
   };
@@ -120,15 +212,19 @@ function App() {
     const fullText = newParagraphs.join(' ');
     if (fullText.trim()) {
       setIsPreparing(true);
+      setProgress(null);
+      jobIdRef.current = null;
       try {
-        const result = await invoke<TtsResult>('prepare_audio', { text: fullText });
-        setWordTimings(result.word_timings);
-        setDurationMs(result.duration_ms);
-        setHasAudio(true);
+        const jobId = await invoke<number>('prepare_audio', { paragraphs: newParagraphs });
+        jobIdRef.current = jobId;
+        const early = earlyJobEventsRef.current.get(jobId);
+        earlyJobEventsRef.current.clear();
+        if (early) {
+          applyJobEvent(early);
+        }
       } catch (err) {
         setError(`Failed to prepare audio: ${err}`);
         console.error('TTS Error:', err);
-      } finally {
         setIsPreparing(false);
       }
     }
@@ -153,17 +249,61 @@ function App() {
     }
   };
 
+  const handleSeek = async (targetMs: number) => {
+    try {
+      await invoke('seek_audio', { positionMs: targetMs });
+      setPositionMs(targetMs);
+    } catch (err) {
+      console.error('Failed to seek:', err);
+    }
+  };
+
+  const handleSkip = async (unit: SkipUnit, count: number) => {
+    try {
+      const newPosition = await invoke<number>('skip_audio', { unit, count });
+      setPositionMs(newPosition);
+    } catch (err) {
+      console.error('Failed to skip:', err);
+    }
+  };
+
+  // Keyboard navigation: arrows skip 15 seconds, with Shift by sentence and
+  // with Alt by paragraph; [ and ] move between chapters
+  useEffect(() => {
+    if (!hasAudio) return;
+    const onKeyDown = (e: KeyboardEvent) => {
+      if (e.target instanceof HTMLInputElement || e.target instanceof HTMLSelectElement) return;
+      const direction = e.key === 'ArrowLeft' || e.key === '[' ? -1 : e.key === 'ArrowRight' || e.key === ']' ? 1 : 0;
+      if (direction === 0) return;
+      e.preventDefault();
+      if (e.key === '[' || e.key === ']') {
+        handleSkip('chapter', direction);
+      } else if (e.shiftKey) {
+        handleSkip('sentence', direction);
+      } else if (e.altKey) {
+        handleSkip('paragraph', direction);
+      } else {
+        handleSkip('seconds', direction * 15);
+      }
+    };
+    window.addEventListener('keydown', onKeyDown);
+    return () => window.removeEventListener('keydown', onKeyDown);
+  }, [hasAudio]);
+
+  // Jump to the start of a word clicked in the teleprompter
+  const handleWordClick = (index: number) => {
+    const timing = wordTimings[index];
+    if (hasAudio && timing) {
+      handleSeek(timing.start_ms);
+    }
+  };
+
   const handleSpeedChange = async (newSpeed: number) => {
     try {
       await invoke('set_speed', { speed: newSpeed });
+      // Timings are measured from the audio itself and the playback
+      // position is reported in audio time, so they stay valid at any speed
       setSpeed(newSpeed);
-      // Recalculate word timings with new speed
-      const fullText = paragraphs.join(' ');
-      const timings = await invoke<WordTiming[]>('get_word_timings', { 
-        text: fullText, 
-        speed: newSpeed 
-      });
-      setWordTimings(timings);
     } catch (err) {
       console.error('Failed to set speed:', err);
     }
@@ -178,6 +318,16 @@ function App() {
     }
   };
 
+  const handleOutputDeviceChange = async (name: string | null) => {
+    try {
+      await invoke('set_output_device', { name });
+      setOutputDevice(name);
+    } catch (err) {
+      setError(`Failed to switch output: ${err}`);
+      loadOutputDevices();
+    }
+  };
+
   const fullText = paragraphs.join(' ');
 
   return (
@@ -216,6 +366,18 @@ function App() {
             <div className="pdf-info">
               <p><strong>{paragraphs.length}</strong> paragraphs</p>
               <p><strong>{fullText.split(/\s+/).length}</strong> words</p>
+              {isPreparing && (
+                <div className="synthesis-progress">
+                  <progress max={100} value={progress?.percent ?? 0} />
+                  {progress && (
+                    <p>
+                      Chunk {progress.completed} of {progress.total}, about{' '}
+                      {Math.ceil(progress.eta_ms / 1000)}s left
+                    </p>
+                  )}
+                  <button onClick={cancelPreparing}>Cancel</button>
+                </div>
+              )}
               <button className="clear-button" onClick={handleClear}>
                 ✕ Clear PDF
               </button>
@@ -227,9 +389,9 @@ function App() {
           <Teleprompter
             text={fullText}
             wordTimings={wordTimings}
-            currentPositionMs={positionMs}
+            currentWordIndex={wordIndex}
             isPlaying={isPlaying}
-            speed={speed}
+            onWordClick={handleWordClick}
           />
         </section>
       </main>
@@ -245,8 +407,13 @@ function App() {
           volume={volume}
           onPlay={handlePlay}
           onPause={handlePause}
+          onSeek={handleSeek}
+          onSkip={handleSkip}
           onSpeedChange={handleSpeedChange}
           onVolumeChange={handleVolumeChange}
+          outputDevices={outputDevices}
+          outputDevice={outputDevice}
+          onOutputDeviceChange={handleOutputDeviceChange}
         />
       </footer>
     </div>
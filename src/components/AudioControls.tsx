@@ -1,5 +1,12 @@
 import { useState, useEffect } from 'react';
 
+export type SkipUnit = 'seconds' | 'sentence' | 'paragraph' | 'chapter';
+
+export interface OutputDevice {
+  name: string;
+  is_default: boolean;
+}
+
 interface AudioControlsProps {
   isPlaying: boolean;
   isPreparing: boolean;
@@ -8,10 +15,16 @@ interface AudioControlsProps {
   durationMs: number;
   speed: number;
   volume: number;
+  outputDevices: OutputDevice[];
+  /** Chosen output, or null to follow the system default */
+  outputDevice: string | null;
   onPlay: () => void;
   onPause: () => void;
+  onSeek: (positionMs: number) => void;
+  onSkip: (unit: SkipUnit, count: number) => void;
   onSpeedChange: (speed: number) => void;
   onVolumeChange: (volume: number) => void;
+  onOutputDeviceChange: (name: string | null) => void;
 }
 
 export function AudioControls({
@@ -22,10 +35,15 @@ export function AudioControls({
   durationMs,
   speed,
   volume,
+  outputDevices,
+  outputDevice,
   onPlay,
   onPause,
+  onSeek,
+  onSkip,
   onSpeedChange,
   onVolumeChange,
+  onOutputDeviceChange,
 }: AudioControlsProps) {
   const formatTime = (ms: number) => {
     const totalSeconds = Math.floor(ms / 1000);
@@ -36,12 +54,29 @@ export function AudioControls({
 
   const progress = durationMs > 0 ? (positionMs / durationMs) * 100 : 0;
 
-  const speedOptions = [0.5, 0.75, 1.0, 1.25, 1.5, 1.75, 2.0];
+  // Seek to the clicked or dragged point on the progress bar
+  const seekFromPointer = (e: React.PointerEvent<HTMLDivElement>) => {
+    if (!hasAudio || durationMs <= 0) return;
+    const rect = e.currentTarget.getBoundingClientRect();
+    const ratio = Math.min(Math.max((e.clientX - rect.left) / rect.width, 0), 1);
+    onSeek(Math.round(ratio * durationMs));
+  };
+
+  const speedOptions = [0.25, 0.5, 0.75, 1.0, 1.25, 1.5, 1.75, 2.0, 2.5, 3.0, 4.0];
 
   return (
     <div className="audio-controls">
       <div className="progress-section">
-        <div className="progress-bar">
+        <div
+          className={`progress-bar ${hasAudio ? 'seekable' : ''}`}
+          onPointerDown={(e) => {
+            e.currentTarget.setPointerCapture(e.pointerId);
+            seekFromPointer(e);
+          }}
+          onPointerMove={(e) => {
+            if (e.currentTarget.hasPointerCapture(e.pointerId)) seekFromPointer(e);
+          }}
+        >
           <div 
             className="progress-fill" 
             style={{ width: `${progress}%` }}
@@ -54,6 +89,30 @@ export function AudioControls({
       </div>
 
       <div className="controls-main">
+        <button
+          className="skip-button"
+          onClick={() => onSkip('paragraph', -1)}
+          disabled={!hasAudio}
+          title="Previous paragraph"
+        >
+          ⏮
+        </button>
+        <button
+          className="skip-button"
+          onClick={() => onSkip('seconds', -15)}
+          disabled={!hasAudio}
+          title="Back 15 seconds"
+        >
+          −15s
+        </button>
+        <button
+          className="skip-button"
+          onClick={() => onSkip('sentence', -1)}
+          disabled={!hasAudio}
+          title="Repeat sentence"
+        >
+          ↺
+        </button>
         <button
           className={`play-button ${isPlaying ? 'playing' : ''}`}
           onClick={isPlaying ? onPause : onPlay}
@@ -72,6 +131,30 @@ export function AudioControls({
             </svg>
           )}
         </button>
+        <button
+          className="skip-button"
+          onClick={() => onSkip('sentence', 1)}
+          disabled={!hasAudio}
+          title="Next sentence"
+        >
+          ↻
+        </button>
+        <button
+          className="skip-button"
+          onClick={() => onSkip('seconds', 15)}
+          disabled={!hasAudio}
+          title="Forward 15 seconds"
+        >
+          +15s
+        </button>
+        <button
+          className="skip-button"
+          onClick={() => onSkip('paragraph', 1)}
+          disabled={!hasAudio}
+          title="Next paragraph"
+        >
+          ⏭
+        </button>
       </div>
 
       <div className="controls-secondary">
@@ -98,6 +181,22 @@ export function AudioControls({
             onChange={(e) => onVolumeChange(parseFloat(e.target.value))}
           />
         </div>
+
+        <div className="device-control">
+          <label>Output</label>
+          <select
+            value={outputDevice ?? ''}
+            onChange={(e) => onOutputDeviceChange(e.target.value || null)}
+          >
+            <option value="">System default</option>
+            {outputDevice && !outputDevices.some(d => d.name === outputDevice) && (
+              <option value={outputDevice}>{outputDevice} (disconnected)</option>
+            )}
+            {outputDevices.map(d => (
+              <option key={d.name} value={d.name}>{d.name}</option>
+            ))}
+          </select>
+        </div>
       </div>
     </div>
   );
@@ -1,4 +1,4 @@
-import { useEffect, useRef, useState, useCallback } from 'react';
+import { useEffect, useRef, useCallback } from 'react';
 
 interface WordTiming {
   word: string;
@@ -9,39 +9,21 @@ interface WordTiming {
 interface TeleprompterProps {
   text: string;
   wordTimings: WordTiming[];
-  currentPositionMs: number;
+  /** Word being spoken, as reported by the audio thread */
+  currentWordIndex: number | null;
   isPlaying: boolean;
-  speed: number;
+  onWordClick?: (index: number) => void;
 }
 
 export function Teleprompter({ 
   text, 
   wordTimings, 
-  currentPositionMs, 
+  currentWordIndex: reportedIndex,
   isPlaying,
-  speed 
+  onWordClick
 }: TeleprompterProps) {
   const containerRef = useRef<HTMLDivElement>(null);
-  const [currentWordIndex, setCurrentWordIndex] = useState(0);
-
-  // Find current word based on position
-  useEffect(() => {
-    if (wordTimings.length === 0) return;
-
-    // Adjust position for speed
-    const adjustedPosition = currentPositionMs;
-    
-    let newIndex = 0;
-    for (let i = 0; i < wordTimings.length; i++) {
-      if (adjustedPosition >= wordTimings[i].start_ms) {
-        newIndex = i;
-      } else {
-        break;
-      }
-    }
-    
-    setCurrentWordIndex(newIndex);
-  }, [currentPositionMs, wordTimings, speed]);
+  const currentWordIndex = reportedIndex ?? 0;
 
   // Auto-scroll to current word
   useEffect(() => {
@@ -94,6 +76,7 @@ export function Teleprompter({
             <span
               key={index}
               className={`word ${isPast ? 'past' : ''} ${isCurrent ? 'current' : ''} ${isUpcoming ? 'upcoming' : ''}`}
+              onClick={onWordClick ? () => onWordClick(index) : undefined}
             >
               {word}{' '}
             </span>